@@ -1,9 +1,8 @@
-use anyhow;
 use parsnip::{
     self, brokers::redis::RedisBroker, messages::Command, task::Signature, task::Task,
-    worker::Worker,
+    worker::Worker, App,
 };
-use std::{env, thread, time};
+use std::{env, sync::Arc, thread, time};
 
 struct HelloWorldTask {
     called_with_signature: Signature<Self>,
@@ -12,6 +11,7 @@ struct HelloWorldTask {
 impl Task for HelloWorldTask {
     type ArgumentType = ();
     type ReturnType = usize;
+    type ErrorType = ();
 
     const ID: &'static str = "HelloWorldTask";
 
@@ -21,9 +21,9 @@ impl Task for HelloWorldTask {
         }
     }
 
-    fn run(_: &Self::ArgumentType) -> Self::ReturnType {
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
         println!("Hello, World!");
-        42
+        Ok(42)
     }
 
     fn signature(&self) -> &Signature<Self> {
@@ -31,26 +31,19 @@ impl Task for HelloWorldTask {
     }
 }
 
-fn controller_main(connect_url: String) -> () {
-    let mut broker = RedisBroker::new(&connect_url).expect("Can not connect to Redis");
-    let mut app = parsnip::App::new(&mut broker);
-    app.register_task::<HelloWorldTask>();
-
+fn controller_main(app: Arc<App<RedisBroker>>) {
     println!("Controller thread: Queueing task");
-    let signature_id = app.queue_task::<HelloWorldTask>(()).unwrap();
-
-    println!("Controller thread: Polling for task run result");
-    let mut result = app.get_task_result(&signature_id).unwrap();
-    while result.is_none() {
-        thread::sleep(time::Duration::from_millis(500));
-        println!("Controller thread: Polling for task run result");
-        result = app.get_task_result(&signature_id).unwrap();
-    }
+    let async_result = app.queue_task::<HelloWorldTask>(()).unwrap();
 
-    println!(
-        "Controller thread: Got task run result, {}",
-        result.unwrap().result
-    );
+    println!("Controller thread: Waiting for task run result");
+    let result = async_result
+        .wait(time::Duration::from_secs(30))
+        .expect("No task run result within 30 seconds");
+
+    match result {
+        Ok(value) => println!("Controller thread: Got task run result, {}", value),
+        Err(error) => println!("Controller thread: Task failed, {:?}", error),
+    };
 
     println!("Controller thread: Looking up all registered workers");
     match app.list_workers().unwrap() {
@@ -68,12 +61,8 @@ fn controller_main(connect_url: String) -> () {
     println!("Controller thread: Done");
 }
 
-fn worker_main(connect_url: String) -> () {
-    let mut broker = RedisBroker::new(&connect_url).expect("Can not connect to Redis");
-    let mut app = parsnip::App::new(&mut broker);
-    app.register_task::<HelloWorldTask>();
-
-    let worker = Worker::new(&app).expect("Worker initalization failed");
+fn worker_main(app: Arc<App<RedisBroker>>) {
+    let worker = Worker::new(app).expect("Worker initalization failed");
     println!("Worker thread: Registered worker with ID {}", worker.id);
     println!("Worker thread: Listening for messages...");
     worker
@@ -87,11 +76,16 @@ fn main() -> Result<(), anyhow::Error> {
         .nth(1)
         .expect("No Redis connect URL passed the first an argument");
 
-    let connect_url_controller = connect_url.clone();
-    let handle_controller_thread = thread::spawn(|| controller_main(connect_url_controller));
+    // One app is shared by both threads.
+    let broker = RedisBroker::new(&connect_url).expect("Can not connect to Redis");
+    let mut app = App::new(broker);
+    app.register_task::<HelloWorldTask>();
+    let app = Arc::new(app);
+
+    let app_controller = app.clone();
+    let handle_controller_thread = thread::spawn(|| controller_main(app_controller));
 
-    let connect_url_worker = connect_url.clone();
-    let handler_worker_thread = thread::spawn(|| worker_main(connect_url_worker));
+    let handler_worker_thread = thread::spawn(|| worker_main(app));
 
     handle_controller_thread.join().unwrap();
     handler_worker_thread.join().unwrap();
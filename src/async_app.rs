@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+use ulid::Ulid;
+
+use super::async_runner::{self, AsyncTaskRunner};
+use super::broker::{AsyncBroker, TaskInfo, TaskState, WorkerInfo};
+use super::context::TaskContext;
+use super::error::Error;
+use super::messages::{Chord, Command, Message, ResultMessage, TaskOutcome};
+use super::options::QueueOptions;
+use super::runner::{self, ChordCompletion};
+use super::task::{AsyncSignature, AsyncTask};
+
+/// The async counterpart of `App`, holding the registered async tasks.
+///
+/// Tasks are queued with the same message format as through `App`, so a task
+/// queued here can be picked up by any worker that has it registered.
+pub struct AsyncApp<B: AsyncBroker> {
+    task_runners: HashMap<String, AsyncTaskRunner<B>>,
+    pub(crate) broker: B,
+}
+
+impl<B: AsyncBroker + 'static> AsyncApp<B> {
+    pub fn new(broker: B) -> Self {
+        Self {
+            task_runners: HashMap::new(),
+            broker,
+        }
+    }
+
+    pub fn register_task<T: AsyncTask>(&mut self) {
+        self.task_runners
+            .insert(T::ID.into(), async_runner::run_task::<T, B>);
+    }
+
+    /// Queue a task for pickup by a worker.
+    ///
+    /// Returns the signature ID of the task invocation.
+    pub async fn queue_task<T: AsyncTask>(&self, arg: T::ArgumentType) -> Result<String, Error> {
+        self.queue_task_with::<T>(arg, QueueOptions::default())
+            .await
+    }
+
+    /// Queue a task for pickup by a worker, with options for when it is run.
+    ///
+    /// Returns the signature ID of the task invocation.
+    pub async fn queue_task_with<T: AsyncTask>(
+        &self,
+        arg: T::ArgumentType,
+        options: QueueOptions,
+    ) -> Result<String, Error> {
+        if !self.task_runners.contains_key(T::ID) {
+            return Err(Error::NotRegistered(T::ID.into()));
+        }
+
+        let signature_id = Ulid::new().to_string();
+        let dedup_key = options.dedup_key_or(T::dedup_key(&arg));
+        let signature = AsyncSignature {
+            arg,
+            id: signature_id.clone(),
+        };
+        let mut message = Message::new(
+            T::ID.into(),
+            signature_id.clone(),
+            serde_json::to_string(&signature)?,
+        );
+        if let Some(dedup_key) = &dedup_key {
+            if let Some(existing_signature_id) = self
+                .broker
+                .claim_dedup_key(T::ID, dedup_key, &signature_id)
+                .await?
+            {
+                // A duplicate is already queued or running.
+                return Ok(existing_signature_id);
+            }
+        }
+        message.dedup_key = dedup_key;
+        message.queue = options.queue_or(T::QUEUE);
+        message.priority = options.priority_or(T::PRIORITY);
+        message.not_before = options.not_before(message.queued_at);
+        message.expires_at = options.expires_at(message.queued_at);
+        let queued = match self
+            .set_task_state(&message, TaskState::Pending, None)
+            .await
+        {
+            Ok(()) => self.push_message(&message).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = queued {
+            // Let the task be queued again, instead of holding the key until
+            // it lapses.
+            if let Some(dedup_key) = &message.dedup_key {
+                self.broker
+                    .release_dedup_key(T::ID, dedup_key, &signature_id)
+                    .await
+                    .unwrap_or_else(|_| {
+                        println!("Unable to release the dedup key '{dedup_key}'.");
+                    });
+            }
+            return Err(error);
+        }
+        Ok(signature_id)
+    }
+
+    pub async fn get_task_result(
+        &self,
+        signature_id: &str,
+    ) -> Result<Option<ResultMessage>, Error> {
+        self.broker.get_result(signature_id).await
+    }
+
+    /// Get the current state of a task invocation, along with which worker
+    /// handled it and when.
+    pub async fn get_task_state(&self, signature_id: &str) -> Result<Option<TaskInfo>, Error> {
+        self.broker.get_task_info(signature_id).await
+    }
+
+    /// Revoke a task invocation, so that workers skip it if they have not
+    /// started on it yet. With `terminate`, the worker running the task is
+    /// also told to stop it, see `App::revoke`.
+    pub async fn revoke(&self, signature_id: &str, terminate: bool) -> Result<(), Error> {
+        self.broker.revoke(signature_id).await?;
+        if !terminate {
+            return Ok(());
+        }
+
+        let running_on = self
+            .broker
+            .get_task_info(signature_id)
+            .await?
+            .filter(|info| matches!(info.state, TaskState::Received | TaskState::Started))
+            .and_then(|info| info.worker_id);
+        if let Some(worker_id) = running_on {
+            self.broker
+                .push_command(&Command::TerminateTask(signature_id.into()), &worker_id)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn queue_command(&self, command: &Command, worker_id: &str) -> Result<(), Error> {
+        self.broker.push_command(command, worker_id).await
+    }
+
+    pub(crate) async fn handle_message(
+        &self,
+        message: &Message,
+        worker_id: &str,
+        context: &TaskContext,
+    ) -> Result<(), Error> {
+        if message.is_expired() {
+            return self
+                .store_task_outcome(message, TaskOutcome::Expired, worker_id)
+                .await;
+        }
+
+        let Some(task_runner) = self.task_runners.get(&message.task_id) else {
+            // Redelivering the message would fail the same way.
+            let error = Error::UnknownTask(message.task_id.clone());
+            return self
+                .store_task_outcome(message, TaskOutcome::Rejected(error.to_string()), worker_id)
+                .await;
+        };
+
+        task_runner(self, message, worker_id, context).await
+    }
+
+    pub(crate) async fn is_revoked(&self, signature_id: &str) -> Result<bool, Error> {
+        self.broker.is_revoked(signature_id).await
+    }
+
+    /// Acknowledge a reserved message that the worker failed to handle,
+    /// putting it back on the queue to be handled again, or rejecting it if
+    /// it has been redelivered too many times already.
+    pub(crate) async fn redeliver_message(
+        &self,
+        message: &Message,
+        worker_id: &str,
+        error: &Error,
+    ) -> Result<(), Error> {
+        match runner::redelivered_message(message) {
+            Some(redelivered_message) => self.requeue_message(&redelivered_message).await?,
+            None => {
+                let outcome = TaskOutcome::Rejected(error.to_string());
+                self.store_task_outcome(message, outcome, worker_id).await?
+            }
+        }
+        self.broker.ack_message(worker_id, message).await
+    }
+
+    /// Put a message back on the queue, for instance to retry a task or
+    /// because it was picked up before it was due.
+    pub(crate) async fn requeue_message(&self, message: &Message) -> Result<(), Error> {
+        self.push_message(message).await
+    }
+
+    /// Push a message to the queue, or to the delayed messages if it is not
+    /// due yet.
+    async fn push_message(&self, message: &Message) -> Result<(), Error> {
+        if message.is_due() {
+            self.broker.push_message(message).await
+        } else {
+            self.broker.push_delayed_message(message).await
+        }
+    }
+
+    pub(crate) async fn set_task_state(
+        &self,
+        message: &Message,
+        state: TaskState,
+        worker_id: Option<&str>,
+    ) -> Result<(), Error> {
+        self.broker
+            .update_task_info(TaskInfo {
+                signature_id: message.signature_id.clone(),
+                state,
+                worker_id: worker_id.map(str::to_string),
+                queued_at: message.queued_at,
+                updated_at: SystemTime::now(),
+            })
+            .await
+    }
+
+    /// Store the outcome of a task invocation, and move it to the matching
+    /// final state. Callbacks, chains and chords are continued or failed as
+    /// by `App`.
+    pub(crate) async fn store_task_outcome(
+        &self,
+        message: &Message,
+        outcome: TaskOutcome,
+        worker_id: &str,
+    ) -> Result<(), Error> {
+        let state = runner::final_state(&outcome);
+        let callback_messages = runner::callback_messages(message, &outcome)?;
+        let next_message = match &outcome {
+            TaskOutcome::Success(value) => runner::next_in_chain(message, value)?,
+            _ => {
+                for (result, info) in runner::upstream_failures(message) {
+                    self.broker.store_result(result).await?;
+                    self.broker.update_task_info(info).await?;
+                }
+                None
+            }
+        };
+
+        self.broker
+            .store_result(ResultMessage {
+                signature_id: message.signature_id.clone(),
+                outcome,
+            })
+            .await?;
+        self.set_task_state(message, state, Some(worker_id)).await?;
+        if let Some(dedup_key) = &message.dedup_key {
+            self.broker
+                .release_dedup_key(&message.task_id, dedup_key, &message.signature_id)
+                .await?;
+        }
+
+        for next_message in next_message.iter().chain(&callback_messages) {
+            self.set_task_state(next_message, TaskState::Pending, None)
+                .await?;
+            self.push_message(next_message).await?;
+        }
+        if let Some(chord) = &message.chord {
+            // The result is stored before counting the task as finished, so
+            // whoever counts the last one sees the results of all of them.
+            // Completing is only recorded once done, so that a redelivered
+            // message completes the chord if completing it failed before.
+            let finished = self
+                .broker
+                .add_finished_chord_task(&chord.id, &message.signature_id)
+                .await?;
+            if finished == chord.header.len() && !self.broker.is_chord_completed(&chord.id).await? {
+                self.complete_chord(chord, message.queued_at).await?;
+                self.broker.set_chord_completed(&chord.id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue or fail the callback of a chord whose header has finished.
+    async fn complete_chord(&self, chord: &Chord, queued_at: SystemTime) -> Result<(), Error> {
+        let mut results = Vec::with_capacity(chord.header.len());
+        for signature_id in &chord.header {
+            results.push(self.broker.get_result(signature_id).await?);
+        }
+        match runner::complete_chord(chord, results, queued_at)? {
+            ChordCompletion::Queue(callback_message) => {
+                self.set_task_state(&callback_message, TaskState::Pending, None)
+                    .await?;
+                self.push_message(&callback_message).await
+            }
+            ChordCompletion::Fail(result, info) => {
+                self.broker.store_result(result).await?;
+                self.broker.update_task_info(info).await
+            }
+        }
+    }
+
+    pub(crate) async fn update_worker_info(&self, info: WorkerInfo) -> Result<(), Error> {
+        self.broker.update_worker_info(info).await
+    }
+
+    pub(crate) async fn remove_worker_info(&self, worker_id: &str) -> Result<(), Error> {
+        self.broker.remove_worker_info(worker_id).await
+    }
+}
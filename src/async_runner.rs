@@ -0,0 +1,120 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Instant;
+use tokio::task::{JoinError, JoinHandle};
+
+use super::async_app::AsyncApp;
+use super::broker::{AsyncBroker, TaskState};
+use super::context::TaskContext;
+use super::error::Error;
+use super::messages::{Message, TaskOutcome};
+use super::runner::{panic_message, rate_limited_message, retry_message};
+use super::task::{AsyncSignature, AsyncTask};
+
+pub type AsyncTaskFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+pub type AsyncTaskRunner<B> =
+    for<'a> fn(&'a AsyncApp<B>, &'a Message, &'a str, &'a TaskContext) -> AsyncTaskFuture<'a>;
+
+/// Run the async task `T` for the given message, storing its result.
+pub fn run_task<'a, T: AsyncTask, B: AsyncBroker + 'static>(
+    app: &'a AsyncApp<B>,
+    message: &'a Message,
+    worker_id: &'a str,
+    context: &'a TaskContext,
+) -> AsyncTaskFuture<'a> {
+    Box::pin(async move {
+        let signature: AsyncSignature<T::ArgumentType> =
+            match serde_json::from_str(&message.signature) {
+                Ok(signature) => signature,
+                Err(error) => {
+                    // Redelivering the message would fail the same way.
+                    let outcome = TaskOutcome::Rejected(Error::from(error).to_string());
+                    return app.store_task_outcome(message, outcome, worker_id).await;
+                }
+            };
+        if let Some(rate_limit) = &T::RATE_LIMIT {
+            if let Some(wait) = app.broker.take_rate_limit_token(T::ID, rate_limit).await? {
+                // Over the limit, run the task once the next run is allowed.
+                app.set_task_state(message, TaskState::Pending, None)
+                    .await?;
+                return app
+                    .requeue_message(&rate_limited_message(message, wait))
+                    .await;
+            }
+        }
+        app.set_task_state(message, TaskState::Started, Some(worker_id))
+            .await?;
+
+        // Run the task as its own Tokio task, so that a panic in it is caught
+        // by the runtime instead of taking down the worker. The panic is
+        // recorded as the outcome of the task.
+        let handle = tokio::spawn(
+            context
+                .clone()
+                .scope(async move { T::run(&signature.arg).await }),
+        );
+        let run_result = run_with_time_limits::<T>(handle, context).await;
+        if context.is_cancelled() && app.is_revoked(&message.signature_id).await? {
+            // The task was terminated, whatever it returned.
+            return app
+                .store_task_outcome(message, TaskOutcome::Revoked, worker_id)
+                .await;
+        }
+        let outcome = match run_result {
+            None => TaskOutcome::TimedOut,
+            Some(Ok(Ok(value))) => TaskOutcome::Success(serde_json::to_string(&value)?),
+            Some(Ok(Err(error))) => {
+                let retry_message = if T::is_retryable(&error) {
+                    retry_message(message, &T::RETRY_POLICY)
+                } else {
+                    None
+                };
+                if let Some(retry_message) = retry_message {
+                    // The result is only stored once the retries are used up.
+                    app.set_task_state(message, TaskState::Retrying, Some(worker_id))
+                        .await?;
+                    return app.requeue_message(&retry_message).await;
+                }
+                TaskOutcome::Failure(serde_json::to_string(&error)?)
+            }
+            Some(Err(join_error)) if join_error.is_panic() => {
+                TaskOutcome::Panic(panic_message(join_error.into_panic()))
+            }
+            Some(Err(join_error)) => return Err(join_error.into()),
+        };
+        app.store_task_outcome(message, outcome, worker_id).await
+    })
+}
+
+type RunResult<T> =
+    Result<Result<<T as AsyncTask>::ReturnType, <T as AsyncTask>::ErrorType>, JoinError>;
+
+/// Wait for the task, cancelling its context when it hits the soft time limit
+/// and aborting it when it hits the hard time limit.
+///
+/// Returns `None` if the task hit the hard time limit.
+async fn run_with_time_limits<T: AsyncTask>(
+    mut handle: JoinHandle<Result<T::ReturnType, T::ErrorType>>,
+    context: &TaskContext,
+) -> Option<RunResult<T>> {
+    let started = Instant::now();
+    if let Some(soft_time_limit) = T::SOFT_TIME_LIMIT {
+        match tokio::time::timeout(soft_time_limit, &mut handle).await {
+            Ok(result) => return Some(result),
+            Err(_) => context.cancel(),
+        }
+    }
+    match T::HARD_TIME_LIMIT {
+        Some(hard_time_limit) => {
+            let remaining = hard_time_limit.saturating_sub(started.elapsed());
+            match tokio::time::timeout(remaining, &mut handle).await {
+                Ok(result) => Some(result),
+                Err(_) => {
+                    handle.abort();
+                    None
+                }
+            }
+        }
+        None => Some(handle.await),
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use ulid::Ulid;
+
+use super::async_app::AsyncApp;
+use super::broker::{AsyncBroker, TaskState, WorkerInfo, WorkerState};
+use super::context::TaskContext;
+use super::error::{Error, Result};
+use super::messages::{Command, Message, TaskOutcome, DEFAULT_QUEUE};
+
+/// The contexts of the tasks running on a worker, by signature ID.
+type RunningTasks = Arc<Mutex<HashMap<String, TaskContext>>>;
+
+const DEFAULT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
+const DEFAULT_VISIBILITY_TIMEOUT: time::Duration = time::Duration::from_secs(60 * 60);
+
+/// How long a running worker stays registered after its last heartbeat. It
+/// sends one every quarter of that, from a task of its own so that long
+/// running tasks or poll intervals don't hold it up.
+const WORKER_TTL: time::Duration = time::Duration::from_secs(60);
+
+/// A worker running async tasks, driving many of them at once on the Tokio
+/// runtime it is run on.
+pub struct AsyncWorker<B: AsyncBroker + 'static> {
+    app: Arc<AsyncApp<B>>,
+    pub id: String,
+    poll_interval: time::Duration,
+    concurrency: usize,
+    visibility_timeout: time::Duration,
+    /// The queues the worker consumes, in order of priority.
+    queues: Vec<String>,
+    running: RunningTasks,
+}
+
+pub struct AsyncWorkerBuilder<B: AsyncBroker + 'static> {
+    app: Arc<AsyncApp<B>>,
+    poll_interval: time::Duration,
+    concurrency: usize,
+    visibility_timeout: time::Duration,
+    queues: Vec<String>,
+}
+
+impl<B: AsyncBroker + 'static> AsyncWorkerBuilder<B> {
+    /// How long the worker waits before checking the queue again when it is
+    /// empty, and how often it checks for commands while at its concurrency
+    /// limit.
+    pub fn poll_interval(mut self, poll_interval: time::Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How many tasks the worker runs at once. Defaults to one.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// The queues the worker takes messages off, in order of priority: a
+    /// message is only taken off a queue when the queues before it are empty.
+    /// Defaults to the default queue.
+    pub fn queues(mut self, queues: &[&str]) -> Self {
+        self.queues = queues.iter().map(|queue| queue.to_string()).collect();
+        self
+    }
+
+    /// How long any worker may hold a reserved message before it is assumed
+    /// to have died, and the message is put back on the queue by this worker.
+    /// Must be longer than any task takes to run. Defaults to an hour.
+    pub fn visibility_timeout(mut self, visibility_timeout: time::Duration) -> Self {
+        self.visibility_timeout = visibility_timeout;
+        self
+    }
+
+    pub async fn build(self) -> Result<AsyncWorker<B>> {
+        let id = Ulid::new().to_string();
+        self.app
+            .update_worker_info(WorkerInfo {
+                state: WorkerState::Pending,
+                id: id.clone(),
+            })
+            .await?;
+
+        Ok(AsyncWorker {
+            app: self.app,
+            id,
+            poll_interval: self.poll_interval,
+            concurrency: self.concurrency,
+            visibility_timeout: self.visibility_timeout,
+            queues: self.queues,
+            running: RunningTasks::default(),
+        })
+    }
+}
+
+impl<B: AsyncBroker + 'static> AsyncWorker<B> {
+    /// Create a new worker instance with the default configuration.
+    pub async fn new(app: Arc<AsyncApp<B>>) -> Result<Self> {
+        Self::builder(app).build().await
+    }
+
+    /// Configure a new worker instance.
+    pub fn builder(app: Arc<AsyncApp<B>>) -> AsyncWorkerBuilder<B> {
+        AsyncWorkerBuilder {
+            app,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            concurrency: 1,
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            queues: vec![DEFAULT_QUEUE.to_string()],
+        }
+    }
+
+    /// Listen for and process queued task messages.
+    ///
+    /// Continually check the queue for new messages and run the corresponding
+    /// tasks, at most `concurrency` at a time. Checks the command queue before
+    /// each new message, and at least every poll interval while at the
+    /// concurrency limit, and obeys the commands there. Keeps processing until
+    /// receiving a stop command, then waits for the running tasks to finish.
+    pub async fn listen_for_messages(&self) -> Result<()> {
+        send_heartbeat(&self.app, &self.id).await?;
+        let heartbeats = tokio::spawn(send_heartbeats(self.app.clone(), self.id.clone()));
+
+        let mut in_flight = JoinSet::new();
+        let result = self.dispatch_messages(&mut in_flight).await;
+
+        // Let the running tasks finish, reporting failures of their messages.
+        let mut results = vec![result];
+        while let Some(joined) = in_flight.join_next().await {
+            match joined {
+                Ok(result) => self.log_message_error(result),
+                Err(error) => results.push(Err(error.into())),
+            }
+        }
+        heartbeats.abort();
+        results.into_iter().collect::<Result<()>>()?;
+
+        self.app
+            .update_worker_info(WorkerInfo {
+                id: self.id.clone(),
+                state: WorkerState::Stopped,
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn take_first_task_in_queue(&self) -> Result<()> {
+        self.app.broker.promote_due_messages(&self.queues).await?;
+        let message = self
+            .app
+            .broker
+            .reserve_message(&self.id, &self.queues)
+            .await?;
+        match message {
+            Some(m) if !m.is_due() => {
+                self.postpone_message(&m).await?;
+                Err(Error::NoDueMessage)
+            }
+            Some(m) => handle_message(&self.app, &self.id, &self.running, &m).await,
+            None => Err(Error::NoDueMessage),
+        }
+    }
+
+    /// Reserve messages and spawn tasks running them until told to stop,
+    /// never running more than the concurrency limit allows.
+    async fn dispatch_messages(&self, in_flight: &mut JoinSet<Result<()>>) -> Result<()> {
+        let permits = Arc::new(Semaphore::new(self.concurrency));
+        loop {
+            while let Some(joined) = in_flight.try_join_next() {
+                // A task panicking outside of the task runner is a bug in the
+                // worker, not a problem with the message.
+                self.log_message_error(joined?);
+            }
+
+            match self.app.broker.pop_command(&self.id).await? {
+                None => (),
+                Some(Command::StopWorker) => {
+                    return Ok(());
+                }
+                Some(Command::TerminateTask(signature_id)) => {
+                    // Ask the task to stop, if it is running on this worker.
+                    if let Some(context) = self
+                        .running
+                        .lock()
+                        .expect("Failed to aquire lock")
+                        .get(&signature_id)
+                    {
+                        context.cancel();
+                    }
+                }
+            };
+
+            // Wait for a running task to finish, but keep checking for
+            // commands while doing so.
+            let permit =
+                match tokio::time::timeout(self.poll_interval, permits.clone().acquire_owned())
+                    .await
+                {
+                    Ok(permit) => permit.expect("The semaphore is never closed"),
+                    Err(_) => continue,
+                };
+
+            self.app.broker.promote_due_messages(&self.queues).await?;
+            match self
+                .app
+                .broker
+                .reserve_message(&self.id, &self.queues)
+                .await?
+            {
+                None => {
+                    // Use the idle time to recover messages held by workers
+                    // that died.
+                    self.app
+                        .broker
+                        .requeue_unacked_messages(self.visibility_timeout)
+                        .await?;
+                    tokio::time::sleep(self.poll_interval).await;
+                }
+                Some(m) if !m.is_due() => {
+                    // The task is scheduled to run later, put it back and
+                    // wait a little so we do not spin on undue messages.
+                    self.postpone_message(&m).await?;
+                    tokio::time::sleep(self.poll_interval).await;
+                }
+                Some(m) => {
+                    let app = self.app.clone();
+                    let worker_id = self.id.clone();
+                    let running = self.running.clone();
+                    in_flight.spawn(async move {
+                        let result = handle_message(&app, &worker_id, &running, &m).await;
+                        drop(permit);
+                        result
+                    });
+                }
+            }
+        }
+    }
+
+    /// Report a failure to handle a message. The message itself has been put
+    /// back on the queue or rejected, so the worker carries on with the next.
+    fn log_message_error(&self, result: Result<()>) {
+        if let Err(error) = result {
+            println!(
+                "Worker ID {} failed to handle a message: {}",
+                &self.id, error
+            );
+        }
+    }
+
+    /// Put a reserved message that is not due yet at the back of the queue.
+    async fn postpone_message(&self, message: &Message) -> Result<()> {
+        self.app.requeue_message(message).await?;
+        self.app.broker.ack_message(&self.id, message).await
+    }
+}
+
+/// Keep the worker registered as running, so that it is not assumed to have
+/// died.
+async fn send_heartbeat<B: AsyncBroker + 'static>(
+    app: &AsyncApp<B>,
+    worker_id: &str,
+) -> Result<()> {
+    let info = WorkerInfo {
+        id: worker_id.to_string(),
+        state: WorkerState::Running,
+    };
+    app.broker.worker_heartbeat(info, WORKER_TTL).await
+}
+
+/// Send a heartbeat every quarter of the worker TTL until aborted. Failing to
+/// send one is reported, and tried again next time.
+async fn send_heartbeats<B: AsyncBroker + 'static>(app: Arc<AsyncApp<B>>, worker_id: String) {
+    loop {
+        tokio::time::sleep(WORKER_TTL / 4).await;
+        if let Err(error) = send_heartbeat(&app, &worker_id).await {
+            println!(
+                "Worker ID {} failed to send a heartbeat: {}",
+                &worker_id, error
+            );
+        }
+    }
+}
+
+/// Handle a reserved message, acknowledging it once done. If handling fails
+/// the message is put back on the queue, unless it has failed too many times
+/// already.
+///
+/// Messages for revoked tasks are acknowledged without running the task.
+async fn handle_message<B: AsyncBroker + 'static>(
+    app: &AsyncApp<B>,
+    worker_id: &str,
+    running: &RunningTasks,
+    message: &Message,
+) -> Result<()> {
+    let result = match app.is_revoked(&message.signature_id).await {
+        Ok(true) => {
+            app.store_task_outcome(message, TaskOutcome::Revoked, worker_id)
+                .await
+        }
+        Ok(false) => run_message(app, worker_id, running, message).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => app.broker.ack_message(worker_id, message).await,
+        Err(e) => {
+            app.redeliver_message(message, worker_id, &e).await?;
+            Err(e)
+        }
+    }
+}
+
+/// Run the task of a message, keeping its context around so the task can be
+/// terminated while it runs.
+async fn run_message<B: AsyncBroker + 'static>(
+    app: &AsyncApp<B>,
+    worker_id: &str,
+    running: &RunningTasks,
+    message: &Message,
+) -> Result<()> {
+    let context = TaskContext::new(message.signature_id.clone());
+    running
+        .lock()
+        .expect("Failed to aquire lock")
+        .insert(message.signature_id.clone(), context.clone());
+
+    let result = match app
+        .set_task_state(message, TaskState::Received, Some(worker_id))
+        .await
+    {
+        Ok(()) => app.handle_message(message, worker_id, &context).await,
+        Err(e) => Err(e),
+    };
+
+    running
+        .lock()
+        .expect("Failed to aquire lock")
+        .remove(&message.signature_id);
+    result
+}
+
+impl<B: AsyncBroker + 'static> Drop for AsyncWorker<B> {
+    fn drop(&mut self) {
+        // Remove the worker from the worker register. This can only be done
+        // from within a runtime, so the removal is spawned on the current one
+        // if there is any.
+        let app = self.app.clone();
+        let id = self.id.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    app.remove_worker_info(&id).await.unwrap_or_else(|_| {
+                        println!("Unable to remove worker info, worker ID {} will linger in worker register even though it is being dropped.", &id);
+                    });
+                });
+            }
+            Err(_) => {
+                println!("Unable to remove worker info outside of a Tokio runtime, worker ID {} will linger in worker register even though it is being dropped.", &id);
+            }
+        }
+    }
+}
@@ -1,6 +1,16 @@
+use super::error::Result;
 use super::messages::{Command, Message, ResultMessage};
-use anyhow::Result;
+use super::task::RateLimit;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+const INITIAL_RESULT_POLL_DELAY: Duration = Duration::from_millis(10);
+const MAX_RESULT_POLL_DELAY: Duration = Duration::from_millis(500);
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum WorkerState {
@@ -15,10 +25,133 @@ pub struct WorkerInfo {
     pub id: String,
 }
 
-pub trait Broker {
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TaskState {
+    /// Queued, but not yet picked up by a worker.
+    Pending,
+    /// Picked up by a worker.
+    Received,
+    /// Being run by a worker.
+    Started,
+    /// Failed and queued to be retried.
+    Retrying,
+    Success,
+    Failure,
+    Revoked,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TaskInfo {
+    pub signature_id: String,
+    pub state: TaskState,
+    /// The worker that last handled the task, if any has.
+    pub worker_id: Option<String>,
+    pub queued_at: SystemTime,
+    /// When the task last transitioned to a new state.
+    pub updated_at: SystemTime,
+}
+
+/// Storage for the queues, results and worker register.
+///
+/// Messages are sent to the queue named by `Message::queue`, and workers
+/// take them off the queues they consume, in order of priority.
+///
+/// Errors from the storage backend should be wrapped with `Error::broker`.
+pub trait Broker: Send + Sync {
     fn push_message(&self, message: &Message) -> Result<()>;
 
-    fn pop_message(&self) -> Result<Option<Message>>;
+    /// Store a message that is not due yet, until it is moved to its queue
+    /// by `promote_due_messages`.
+    ///
+    /// The default implementation pushes the message straight to the queue,
+    /// leaving it to the workers to put it back until it is due.
+    fn push_delayed_message(&self, message: &Message) -> Result<()> {
+        self.push_message(message)
+    }
+
+    /// Move the delayed messages for any of `queues` that have become due to
+    /// their queue.
+    ///
+    /// Returns the number of messages moved.
+    fn promote_due_messages(&self, _queues: &[String]) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Take the next message off the first of `queues` that is not empty,
+    /// reserving it for the worker.
+    ///
+    /// The message is held by the broker until the worker acknowledges or
+    /// rejects it, so that it is not lost if the worker dies while handling it.
+    fn reserve_message(&self, worker_id: &str, queues: &[String]) -> Result<Option<Message>>;
+
+    /// Reserve the next message, waiting up to `timeout` for one to arrive.
+    ///
+    /// The default implementation checks the queues once, and sleeps for
+    /// `timeout` if they are empty. Brokers that can block until a message
+    /// arrives should override this, and should stop waiting early when a
+    /// command is pushed for the worker.
+    fn reserve_message_blocking(
+        &self,
+        worker_id: &str,
+        queues: &[String],
+        timeout: Duration,
+    ) -> Result<Option<Message>> {
+        let message = self.reserve_message(worker_id, queues)?;
+        if message.is_none() {
+            thread::sleep(timeout);
+        }
+        Ok(message)
+    }
+
+    /// Acknowledge that a reserved message has been handled, removing it for
+    /// good.
+    fn ack_message(&self, worker_id: &str, message: &Message) -> Result<()>;
+
+    /// Reject a reserved message, putting it back at the front of its queue.
+    fn nack_message(&self, worker_id: &str, message: &Message) -> Result<()>;
+
+    /// Put reserved messages back on their queues if the worker holding them
+    /// is no longer registered, or they have been reserved for longer than
+    /// `visibility_timeout`.
+    ///
+    /// Returns the number of messages put back.
+    fn requeue_unacked_messages(&self, visibility_timeout: Duration) -> Result<usize>;
+
+    /// Claim `dedup_key` for the invocation `signature_id` of the task
+    /// `task_id`, unless another invocation of the task holds it.
+    ///
+    /// Returns the signature ID of the invocation holding the key, if it was
+    /// not claimed. Brokers may let claims lapse after a while, as long as
+    /// that is well after the task would have been run.
+    fn claim_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> Result<Option<String>>;
+
+    /// Release `dedup_key` for the task `task_id`, if the invocation
+    /// `signature_id` holds it.
+    fn release_dedup_key(&self, task_id: &str, dedup_key: &str, signature_id: &str) -> Result<()>;
+
+    /// Take a token from the bucket enforcing `rate_limit` for the task
+    /// `task_id`, shared by all workers. A new bucket starts out full.
+    ///
+    /// Returns `None` if a token was taken, or else how long until the next
+    /// token is added.
+    fn take_rate_limit_token(
+        &self,
+        task_id: &str,
+        rate_limit: &RateLimit,
+    ) -> Result<Option<Duration>>;
+
+    /// Record that the task invocation is revoked, so that workers skip it.
+    ///
+    /// Brokers may forget revocations after a while, as long as that is well
+    /// after the task would have been run.
+    fn revoke(&self, signature_id: &str) -> Result<()>;
+
+    fn is_revoked(&self, signature_id: &str) -> Result<bool>;
 
     fn push_command(&self, command: &Command, worker_id: &str) -> Result<()>;
 
@@ -26,13 +159,354 @@ pub trait Broker {
 
     fn store_result(&self, result_message: ResultMessage) -> Result<()>;
 
+    /// Atomically record that the task invocation `signature_id` in the
+    /// header of a chord has finished, returning the number of finished
+    /// tasks. Recording the same invocation again, such as for a redelivered
+    /// message, doesn't change the count.
+    fn add_finished_chord_task(&self, chord_id: &str, signature_id: &str) -> Result<usize>;
+
+    /// Whether the callback of a chord was queued, or failed, after its
+    /// header finished.
+    fn is_chord_completed(&self, chord_id: &str) -> Result<bool>;
+
+    /// Record that the callback of a chord was queued, or failed, so that a
+    /// redelivered message of its header doesn't complete it again.
+    fn set_chord_completed(&self, chord_id: &str) -> Result<()>;
+
     fn get_result(&self, signature_id: &str) -> Result<Option<ResultMessage>>;
 
+    /// Get the results of several task invocations at once, in the order of
+    /// `signature_ids`.
+    ///
+    /// The default implementation calls `get_result` for each invocation.
+    fn get_results(&self, signature_ids: &[String]) -> Result<Vec<Option<ResultMessage>>> {
+        signature_ids
+            .iter()
+            .map(|signature_id| self.get_result(signature_id))
+            .collect()
+    }
+
+    /// Wait up to `timeout` for the result of a task invocation to be stored.
+    ///
+    /// The default implementation polls `get_result`, backing off between
+    /// each attempt. Brokers that can be notified when a result is stored
+    /// should override this.
+    fn wait_for_result(
+        &self,
+        signature_id: &str,
+        timeout: Duration,
+    ) -> Result<Option<ResultMessage>> {
+        let deadline = Instant::now() + timeout;
+        let mut delay = INITIAL_RESULT_POLL_DELAY;
+        loop {
+            if let Some(result) = self.get_result(signature_id)? {
+                return Ok(Some(result));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(delay.min(deadline - now));
+            delay = (delay * 2).min(MAX_RESULT_POLL_DELAY);
+        }
+    }
+
+    /// Remove both the stored result and task info for the invocation.
+    fn forget_result(&self, signature_id: &str) -> Result<()>;
+
+    fn update_task_info(&self, info: TaskInfo) -> Result<()>;
+
+    fn get_task_info(&self, signature_id: &str) -> Result<Option<TaskInfo>>;
+
     fn update_worker_info(&self, info: WorkerInfo) -> Result<()>;
 
+    /// Register the worker like `update_worker_info`, but only for `ttl`.
+    /// Workers send these regularly while running, so that one that died
+    /// without unregistering is dropped from the register once its last
+    /// heartbeat expires, and the messages it held are put back on their
+    /// queues.
+    fn worker_heartbeat(&self, info: WorkerInfo, ttl: Duration) -> Result<()>;
+
     fn remove_worker_info(&self, worker_id: &str) -> Result<()>;
 
     fn get_worker_info(&self, worker_id: &str) -> Result<Option<WorkerInfo>>;
 
     fn all_workers(&self) -> Result<Option<Vec<WorkerInfo>>>;
+
+    /// Take the lock that makes `scheduler_id` the only active scheduler, or
+    /// extend it if the scheduler already holds it, for `ttl`.
+    ///
+    /// Returns whether the scheduler holds the lock.
+    fn acquire_scheduler_lock(&self, scheduler_id: &str, ttl: Duration) -> Result<bool>;
+
+    /// Release the scheduler lock, if `scheduler_id` holds it.
+    fn release_scheduler_lock(&self, scheduler_id: &str) -> Result<()>;
+
+    /// When the periodic task with the given schedule name was last due.
+    fn get_schedule_last_run(&self, name: &str) -> Result<Option<SystemTime>>;
+
+    /// Atomically set when the periodic task with the given schedule name was
+    /// last due to `last_run`, if it is still `previous_run` and
+    /// `scheduler_id` holds the scheduler lock.
+    ///
+    /// Returns whether it was set. Schedulers set it before queueing the
+    /// task, so that a scheduler that lost the lock in the meantime doesn't
+    /// queue a run another one already queued.
+    fn advance_schedule_last_run(
+        &self,
+        name: &str,
+        previous_run: Option<SystemTime>,
+        last_run: SystemTime,
+        scheduler_id: &str,
+    ) -> Result<bool>;
+}
+
+/// The async counterpart of `Broker`, used by `AsyncApp` and `AsyncWorker`.
+///
+/// Messages, results and task info are stored the same way as through
+/// `Broker`, so brokers implementing both can be shared by sync and async
+/// workers.
+#[cfg(feature = "async")]
+pub trait AsyncBroker: Send + Sync {
+    fn push_message(&self, message: &Message) -> impl Future<Output = Result<()>> + Send;
+
+    /// Store a message that is not due yet, until it is moved to its queue
+    /// by `promote_due_messages`. Defaults to pushing it straight to the
+    /// queue.
+    fn push_delayed_message(&self, message: &Message) -> impl Future<Output = Result<()>> + Send {
+        self.push_message(message)
+    }
+
+    /// Move the delayed messages for any of `queues` that have become due to
+    /// their queue.
+    fn promote_due_messages(
+        &self,
+        _queues: &[String],
+    ) -> impl Future<Output = Result<usize>> + Send {
+        async { Ok(0) }
+    }
+
+    /// Take the next message off the first of `queues` that is not empty,
+    /// reserving it for the worker.
+    fn reserve_message(
+        &self,
+        worker_id: &str,
+        queues: &[String],
+    ) -> impl Future<Output = Result<Option<Message>>> + Send;
+
+    /// Acknowledge that a reserved message has been handled, removing it for
+    /// good.
+    fn ack_message(
+        &self,
+        worker_id: &str,
+        message: &Message,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Reject a reserved message, putting it back at the front of its queue.
+    fn nack_message(
+        &self,
+        worker_id: &str,
+        message: &Message,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Put reserved messages back on their queues if the worker holding them
+    /// is no longer registered, or they have been reserved for longer than
+    /// `visibility_timeout`.
+    fn requeue_unacked_messages(
+        &self,
+        visibility_timeout: Duration,
+    ) -> impl Future<Output = Result<usize>> + Send;
+
+    /// Claim `dedup_key` for the invocation `signature_id` of the task
+    /// `task_id`. Returns the invocation holding the key if it was not
+    /// claimed.
+    fn claim_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> impl Future<Output = Result<Option<String>>> + Send;
+
+    /// Release `dedup_key` for the task `task_id`, if the invocation
+    /// `signature_id` holds it.
+    fn release_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Take a token from the bucket enforcing `rate_limit` for the task
+    /// `task_id`. Returns how long until the next token is added if there
+    /// was none to take.
+    fn take_rate_limit_token(
+        &self,
+        task_id: &str,
+        rate_limit: &RateLimit,
+    ) -> impl Future<Output = Result<Option<Duration>>> + Send;
+
+    /// Record that the task invocation is revoked, so that workers skip it.
+    fn revoke(&self, signature_id: &str) -> impl Future<Output = Result<()>> + Send;
+
+    fn is_revoked(&self, signature_id: &str) -> impl Future<Output = Result<bool>> + Send;
+
+    fn push_command(
+        &self,
+        command: &Command,
+        worker_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn pop_command(&self, worker_id: &str) -> impl Future<Output = Result<Option<Command>>> + Send;
+
+    fn store_result(
+        &self,
+        result_message: ResultMessage,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn add_finished_chord_task(
+        &self,
+        chord_id: &str,
+        signature_id: &str,
+    ) -> impl Future<Output = Result<usize>> + Send;
+
+    fn is_chord_completed(&self, chord_id: &str) -> impl Future<Output = Result<bool>> + Send;
+
+    fn set_chord_completed(&self, chord_id: &str) -> impl Future<Output = Result<()>> + Send;
+
+    fn get_result(
+        &self,
+        signature_id: &str,
+    ) -> impl Future<Output = Result<Option<ResultMessage>>> + Send;
+
+    fn update_task_info(&self, info: TaskInfo) -> impl Future<Output = Result<()>> + Send;
+
+    fn get_task_info(
+        &self,
+        signature_id: &str,
+    ) -> impl Future<Output = Result<Option<TaskInfo>>> + Send;
+
+    fn update_worker_info(&self, info: WorkerInfo) -> impl Future<Output = Result<()>> + Send;
+
+    fn worker_heartbeat(
+        &self,
+        info: WorkerInfo,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn remove_worker_info(&self, worker_id: &str) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Lets a broker be shared, for instance between an `App` and an `AsyncApp`.
+#[cfg(feature = "async")]
+impl<B: AsyncBroker> AsyncBroker for Arc<B> {
+    async fn push_message(&self, message: &Message) -> Result<()> {
+        B::push_message(self, message).await
+    }
+
+    async fn push_delayed_message(&self, message: &Message) -> Result<()> {
+        B::push_delayed_message(self, message).await
+    }
+
+    async fn promote_due_messages(&self, queues: &[String]) -> Result<usize> {
+        B::promote_due_messages(self, queues).await
+    }
+
+    async fn reserve_message(&self, worker_id: &str, queues: &[String]) -> Result<Option<Message>> {
+        B::reserve_message(self, worker_id, queues).await
+    }
+
+    async fn ack_message(&self, worker_id: &str, message: &Message) -> Result<()> {
+        B::ack_message(self, worker_id, message).await
+    }
+
+    async fn nack_message(&self, worker_id: &str, message: &Message) -> Result<()> {
+        B::nack_message(self, worker_id, message).await
+    }
+
+    async fn requeue_unacked_messages(&self, visibility_timeout: Duration) -> Result<usize> {
+        B::requeue_unacked_messages(self, visibility_timeout).await
+    }
+
+    async fn claim_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> Result<Option<String>> {
+        B::claim_dedup_key(self, task_id, dedup_key, signature_id).await
+    }
+
+    async fn release_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> Result<()> {
+        B::release_dedup_key(self, task_id, dedup_key, signature_id).await
+    }
+
+    async fn take_rate_limit_token(
+        &self,
+        task_id: &str,
+        rate_limit: &RateLimit,
+    ) -> Result<Option<Duration>> {
+        B::take_rate_limit_token(self, task_id, rate_limit).await
+    }
+
+    async fn revoke(&self, signature_id: &str) -> Result<()> {
+        B::revoke(self, signature_id).await
+    }
+
+    async fn is_revoked(&self, signature_id: &str) -> Result<bool> {
+        B::is_revoked(self, signature_id).await
+    }
+
+    async fn push_command(&self, command: &Command, worker_id: &str) -> Result<()> {
+        B::push_command(self, command, worker_id).await
+    }
+
+    async fn pop_command(&self, worker_id: &str) -> Result<Option<Command>> {
+        B::pop_command(self, worker_id).await
+    }
+
+    async fn store_result(&self, result_message: ResultMessage) -> Result<()> {
+        B::store_result(self, result_message).await
+    }
+
+    async fn add_finished_chord_task(&self, chord_id: &str, signature_id: &str) -> Result<usize> {
+        B::add_finished_chord_task(self, chord_id, signature_id).await
+    }
+
+    async fn is_chord_completed(&self, chord_id: &str) -> Result<bool> {
+        B::is_chord_completed(self, chord_id).await
+    }
+
+    async fn set_chord_completed(&self, chord_id: &str) -> Result<()> {
+        B::set_chord_completed(self, chord_id).await
+    }
+
+    async fn get_result(&self, signature_id: &str) -> Result<Option<ResultMessage>> {
+        B::get_result(self, signature_id).await
+    }
+
+    async fn update_task_info(&self, info: TaskInfo) -> Result<()> {
+        B::update_task_info(self, info).await
+    }
+
+    async fn get_task_info(&self, signature_id: &str) -> Result<Option<TaskInfo>> {
+        B::get_task_info(self, signature_id).await
+    }
+
+    async fn update_worker_info(&self, info: WorkerInfo) -> Result<()> {
+        B::update_worker_info(self, info).await
+    }
+
+    async fn worker_heartbeat(&self, info: WorkerInfo, ttl: Duration) -> Result<()> {
+        B::worker_heartbeat(self, info, ttl).await
+    }
+
+    async fn remove_worker_info(&self, worker_id: &str) -> Result<()> {
+        B::remove_worker_info(self, worker_id).await
+    }
 }
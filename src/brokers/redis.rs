@@ -1,15 +1,212 @@
-use crate::broker::{Broker, WorkerInfo};
+#[cfg(feature = "async")]
+use crate::broker::AsyncBroker;
+use crate::broker::{Broker, TaskInfo, WorkerInfo};
+use crate::error::{Error, Result};
+use crate::messages::{DEFAULT_PRIORITY, DEFAULT_QUEUE, MAX_PRIORITY};
+use crate::task::RateLimit;
 
-use anyhow::Result;
+#[cfg(feature = "async")]
+use redis::AsyncCommands;
 use redis::{self, Commands};
 use serde_json;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long the notification that a result was stored is kept around for
+/// anyone waiting on it.
+const RESULT_NOTIFICATION_TTL_SECONDS: i64 = 60;
+
+/// How long each revocation is kept.
+const REVOKED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long the set of finished tasks in the header of a chord is kept,
+/// refreshed every time a task is added, and how long a chord is remembered
+/// to be completed.
+const CHORD_FINISHED_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+/// How long a dedup key is held at most, in case the task holding it is
+/// never finished.
+const DEDUP_KEY_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+/// The most delayed messages moved to the queue at once.
+const MAX_PROMOTED_MESSAGES: usize = 100;
+
+/// Atomically move the delayed messages scored at or before `ARGV[1]` from
+/// the sorted set `KEYS[1]` to the list for their priority, the list key
+/// `ARGV[3]` suffixed with the priority capped at `ARGV[4]`, in the order
+/// they are due. Messages without a priority, or that can't be decoded, get
+/// `ARGV[5]`. Wakes up a worker
+/// waiting on the notification list `KEYS[2]`.
+const PROMOTE_DUE_MESSAGES_SCRIPT: &str = r"
+local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[2])
+for _, message in ipairs(due) do
+    local decoded, envelope = pcall(cjson.decode, message)
+    local priority = decoded and type(envelope) == 'table' and envelope.priority
+    priority = tonumber(priority) or tonumber(ARGV[5])
+    priority = math.min(priority, tonumber(ARGV[4]))
+    redis.call('ZREM', KEYS[1], message)
+    redis.call('LPUSH', ARGV[3] .. '_' .. priority, message)
+end
+if #due > 0 then
+    redis.call('LPUSH', KEYS[2], 1)
+    redis.call('LTRIM', KEYS[2], 0, 0)
+end
+return #due
+";
+
+/// Atomically move the next message off the first non-empty list of
+/// `KEYS[1]` up to `KEYS[#KEYS - 2]` to the processing list `KEYS[#KEYS - 1]`,
+/// and add it to the sorted set of reservations `KEYS[#KEYS]` prefixed with
+/// `ARGV[1]` and scored with the time of reservation `ARGV[2]`.
+const RESERVE_MESSAGE_SCRIPT: &str = r"
+for i = 1, #KEYS - 2 do
+    local message = redis.call('LMOVE', KEYS[i], KEYS[#KEYS - 1], 'RIGHT', 'LEFT')
+    if message then
+        redis.call('ZADD', KEYS[#KEYS], ARGV[2], ARGV[1] .. message)
+        return message
+    end
+end
+return false
+";
+
+/// Put a message reserved by a worker back at the front of its list `KEYS[3]`,
+/// taking it out of the worker's processing list `KEYS[2]`, and wake up a
+/// worker waiting on the notification list `KEYS[4]`. Only done if the
+/// reservation `ARGV[1]` is still in the sorted set of reservations `KEYS[1]`,
+/// so that a message acknowledged or requeued by someone else in the meantime
+/// is not queued again. `ARGV[2]` is the message.
+///
+/// Returns 1 if the message was requeued, or else 0.
+const REQUEUE_RESERVATION_SCRIPT: &str = r"
+if redis.call('ZREM', KEYS[1], ARGV[1]) == 0 then
+    return 0
+end
+redis.call('LREM', KEYS[2], 1, ARGV[2])
+redis.call('RPUSH', KEYS[3], ARGV[2])
+redis.call('LPUSH', KEYS[4], 1)
+redis.call('LTRIM', KEYS[4], 0, 0)
+return 1
+";
+
+/// Remove the workers whose registration expired at or before `ARGV[1]`
+/// from the sorted set of expiry times `KEYS[1]` and the register `KEYS[2]`.
+///
+/// Returns the number of workers removed.
+const EXPIRE_WORKERS_SCRIPT: &str = r"
+local expired = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1])
+for _, worker_id in ipairs(expired) do
+    redis.call('HDEL', KEYS[2], worker_id)
+end
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', ARGV[1])
+return #expired
+";
+
+/// Take a token from the rate limit bucket `KEYS[1]`, which holds up to
+/// `ARGV[1]` tokens and is refilled evenly over `ARGV[2]` milliseconds. Uses
+/// the server's clock, so that all workers agree on how full the bucket is.
+///
+/// Returns 0 if a token was taken, or else the milliseconds until the next
+/// token is added.
+const TAKE_RATE_LIMIT_TOKEN_SCRIPT: &str = r"
+local capacity = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + tonumber(time[2]) / 1000
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'refilled_at')
+local tokens = tonumber(bucket[1]) or capacity
+local refilled_at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + (now - refilled_at) * capacity / period)
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) * period / capacity)
+end
+redis.call('HSET', KEYS[1], 'tokens', tokens, 'refilled_at', now)
+redis.call('PEXPIRE', KEYS[1], period)
+return wait
+";
+
+/// Take the lock `KEYS[1]` for the scheduler `ARGV[1]` for `ARGV[2]`
+/// milliseconds, or extend it if the scheduler already holds it.
+const ACQUIRE_SCHEDULER_LOCK_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+    return 1
+end
+return 0
+";
+
+/// Set the field `ARGV[2]` of the hash of last runs `KEYS[2]` to `ARGV[4]`, if
+/// it is still `ARGV[3]`, an empty string if it isn't set, and the scheduler
+/// `ARGV[1]` holds the lock `KEYS[1]`.
+///
+/// Returns 1 if the field was set, or else 0.
+const ADVANCE_SCHEDULE_LAST_RUN_SCRIPT: &str = r"
+local last_run = redis.call('HGET', KEYS[2], ARGV[2]) or ''
+if redis.call('GET', KEYS[1]) ~= ARGV[1] or last_run ~= ARGV[3] then
+    return 0
+end
+redis.call('HSET', KEYS[2], ARGV[2], ARGV[4])
+return 1
+";
+
+/// Delete the key `KEYS[1]` if it is held by `ARGV[1]`, such as the lock held
+/// by a scheduler.
+const RELEASE_KEY_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+/// Set the key `KEYS[1]` to `ARGV[1]` for `ARGV[2]` seconds, unless it is
+/// already set.
+///
+/// Returns the value the key is already set to, if any.
+const CLAIM_DEDUP_KEY_SCRIPT: &str = r"
+local holder = redis.call('GET', KEYS[1])
+if holder then
+    return holder
+end
+redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+return false
+";
 
 pub struct RedisBroker {
     redis_client: redis::Client,
-    queue: String,
+    queue_prefix: String,
+    delayed_queue_prefix: String,
+    /// The list all messages were queued on before queues were named and
+    /// prioritized. Messages left on it by older versions are taken off as
+    /// part of the default queue, after its own messages.
+    legacy_queue: String,
+    /// The sorted set all delayed messages were kept in before queues were
+    /// named, promoted to the default queue.
+    legacy_delayed_queue: String,
+    queue_notification_prefix: String,
+    processing_queue_prefix: String,
+    /// The list reserved entries that can't be decoded as messages are moved
+    /// to, for inspection.
+    dead_letter_queue: String,
+    reservation_sorted_set: String,
+    blocked_client_hash_map: String,
     command_queue_prefix: String,
     result_hash_map: String,
+    result_notification_prefix: String,
+    chord_finished_prefix: String,
+    chord_completed_prefix: String,
+    rate_limit_prefix: String,
+    dedup_key_prefix: String,
+    revoked_sorted_set: String,
+    scheduler_lock: String,
+    schedule_last_run_hash_map: String,
+    task_info_hash_map: String,
     worker_register: String,
+    worker_expiry_sorted_set: String,
 }
 
 impl RedisBroker {
@@ -18,37 +215,520 @@ impl RedisBroker {
 
         Ok(Self {
             redis_client,
-            queue: "parsnip_queue".to_string(),
+            queue_prefix: "parsnip_queue".to_string(),
+            delayed_queue_prefix: "parsnip_delayed_queue".to_string(),
+            legacy_queue: "parsnip_queue".to_string(),
+            legacy_delayed_queue: "parsnip_delayed_queue".to_string(),
+            queue_notification_prefix: "parsnip_queue_ready".to_string(),
+            processing_queue_prefix: "parsnip_processing".to_string(),
+            dead_letter_queue: "parsnip_dead_letter".to_string(),
+            reservation_sorted_set: "parsnip_reserved_messages".to_string(),
+            blocked_client_hash_map: "parsnip_blocked_clients".to_string(),
             command_queue_prefix: "parsnip_command_queue".to_string(),
             result_hash_map: "parsnip_task_results".to_string(),
+            result_notification_prefix: "parsnip_result_ready".to_string(),
+            chord_finished_prefix: "parsnip_chord_finished".to_string(),
+            chord_completed_prefix: "parsnip_chord_completed".to_string(),
+            rate_limit_prefix: "parsnip_rate_limit".to_string(),
+            dedup_key_prefix: "parsnip_dedup".to_string(),
+            revoked_sorted_set: "parsnip_revoked_at".to_string(),
+            scheduler_lock: "parsnip_scheduler_lock".to_string(),
+            schedule_last_run_hash_map: "parsnip_schedule_last_run".to_string(),
+            task_info_hash_map: "parsnip_task_info".to_string(),
             worker_register: "worker_register".to_string(),
+            worker_expiry_sorted_set: "worker_register_expiry".to_string(),
         })
     }
 }
 
+fn unix_time_millis() -> Result<u64> {
+    unix_millis(SystemTime::now())
+}
+
+fn unix_millis(time: SystemTime) -> Result<u64> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).map_err(Error::broker)?;
+    Ok(since_epoch.as_millis() as u64)
+}
+
+/// The score at or before which revocations in the sorted set of revoked task
+/// invocations have expired, given the current time `now`. Expired
+/// revocations are pruned whenever a task is revoked.
+fn revoked_since(now: u64) -> u64 {
+    now.saturating_sub(REVOKED_TTL.as_millis() as u64)
+}
+
+/// The timeout in seconds to pass to BLPOP, which has millisecond precision.
+/// Shorter timeouts are rounded up, as they would otherwise round down to 0
+/// and block forever.
+fn blpop_timeout_seconds(timeout: Duration) -> f64 {
+    timeout.max(Duration::from_millis(1)).as_secs_f64()
+}
+
+/// The score of a delayed message in the sorted set, which is when it is due.
+fn due_score(message: &crate::messages::Message) -> Result<u64> {
+    message
+        .not_before
+        .map_or_else(unix_time_millis, unix_millis)
+}
+
+impl RedisBroker {
+    /// The list of messages with `priority` on `queue`. Each priority has
+    /// its own list, so that higher priority messages can be taken first.
+    fn queue_key(&self, queue: &str, priority: u8) -> String {
+        format!(
+            "{}_{}_{}",
+            self.queue_prefix,
+            queue,
+            priority.min(MAX_PRIORITY)
+        )
+    }
+
+    /// The list a token is pushed to whenever a message is queued on `queue`,
+    /// for workers waiting for one to block on.
+    fn queue_notification_key(&self, queue: &str) -> String {
+        format!("{}_{}", self.queue_notification_prefix, queue)
+    }
+
+    /// Queue a message as part of `pipe`, at the back or front of its list,
+    /// and wake up a worker waiting for one. At most one token is kept in the
+    /// notification list, as the token only says there may be messages.
+    fn queue_message(
+        &self,
+        pipe: &mut redis::Pipeline,
+        message: &crate::messages::Message,
+        message_as_str: &str,
+        at_front: bool,
+    ) {
+        let queue_key = self.queue_key(&message.queue, message.priority);
+        if at_front {
+            pipe.rpush(queue_key, message_as_str);
+        } else {
+            pipe.lpush(queue_key, message_as_str);
+        }
+        let notification_key = self.queue_notification_key(&message.queue);
+        pipe.lpush(&notification_key, 1)
+            .ltrim(&notification_key, 0, 0);
+    }
+
+    /// The script that reserves the next message off `queues` for the
+    /// worker, taking the highest priority message of the first queue that
+    /// is not empty.
+    fn reserve_message_invocation<'a>(
+        &self,
+        script: &'a redis::Script,
+        worker_id: &str,
+        queues: &[String],
+    ) -> Result<redis::ScriptInvocation<'a>> {
+        let mut invocation = script.prepare_invoke();
+        for queue in queues {
+            for priority in (0..=MAX_PRIORITY).rev() {
+                invocation.key(self.queue_key(queue, priority));
+            }
+            if queue == DEFAULT_QUEUE {
+                invocation.key(&self.legacy_queue);
+            }
+        }
+        invocation
+            .key(self.processing_queue_key(worker_id))
+            .key(&self.reservation_sorted_set)
+            .arg(self.reservation_member(worker_id, ""))
+            .arg(unix_time_millis()?);
+        Ok(invocation)
+    }
+
+    /// Deserialize the message the worker reserved, if it got one. An entry
+    /// that can't be deserialized is dead-lettered instead.
+    fn reserved_message(
+        &self,
+        con: &mut redis::Connection,
+        worker_id: &str,
+        serialized_message: Option<String>,
+    ) -> Result<Option<crate::messages::Message>> {
+        let Some(stored) = serialized_message else {
+            return Ok(None);
+        };
+        let message = match crate::messages::Message::from_json(&stored) {
+            Ok(message) => message,
+            Err(e) => {
+                self.dead_letter(worker_id, &stored, &e).exec(con)?;
+                return Ok(None);
+            }
+        };
+        if let Some(pipe) = self.normalize_reservation(worker_id, &stored, &message)? {
+            pipe.exec(con)?;
+        }
+        Ok(Some(message))
+    }
+
+    /// The commands taking an entry reserved by `worker_id` that can't be
+    /// deserialized as a message out of its processing list and the
+    /// reservations, and moving it to the dead-letter list. Otherwise it
+    /// would fail every worker reserving or requeueing it.
+    fn dead_letter(
+        &self,
+        worker_id: &str,
+        stored: &str,
+        error: &serde_json::Error,
+    ) -> redis::Pipeline {
+        println!(
+            "Worker ID {worker_id} reserved a message that can't be deserialized, moving it to the dead-letter list: {error}"
+        );
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .lrem(self.processing_queue_key(worker_id), 1, stored)
+            .zrem(
+                &self.reservation_sorted_set,
+                self.reservation_member(worker_id, stored),
+            )
+            .lpush(&self.dead_letter_queue, stored);
+        pipe
+    }
+
+    /// Messages queued by older versions lack the fields added since, so
+    /// they serialize differently than they were stored. Returns the commands
+    /// storing the reservation of such a message as it now serializes, so
+    /// that acknowledging it finds it.
+    fn normalize_reservation(
+        &self,
+        worker_id: &str,
+        stored: &str,
+        message: &crate::messages::Message,
+    ) -> Result<Option<redis::Pipeline>> {
+        let message_as_str = serde_json::to_string(message)?;
+        if message_as_str == stored {
+            return Ok(None);
+        }
+
+        let processing_queue = self.processing_queue_key(worker_id);
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .lrem(&processing_queue, 1, stored)
+            .lpush(&processing_queue, &message_as_str)
+            .zrem(
+                &self.reservation_sorted_set,
+                self.reservation_member(worker_id, stored),
+            )
+            .zadd(
+                &self.reservation_sorted_set,
+                self.reservation_member(worker_id, &message_as_str),
+                unix_time_millis()?,
+            );
+        Ok(Some(pipe))
+    }
+
+    /// The member of the sorted set of reservations for `message_as_str`
+    /// reserved by `worker_id`. Each message reserved is a separate member,
+    /// scored with when it was reserved.
+    fn reservation_member(&self, worker_id: &str, message_as_str: &str) -> String {
+        format!("{worker_id}:{message_as_str}")
+    }
+
+    /// The script putting the message `message_as_str`, reserved by
+    /// `worker_id` as `reservation`, back at the front of its queue.
+    fn requeue_reservation_invocation<'a>(
+        &self,
+        script: &'a redis::Script,
+        worker_id: &str,
+        reservation: &str,
+        message: &crate::messages::Message,
+        message_as_str: &str,
+    ) -> redis::ScriptInvocation<'a> {
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(&self.reservation_sorted_set)
+            .key(self.processing_queue_key(worker_id))
+            .key(self.queue_key(&message.queue, message.priority))
+            .key(self.queue_notification_key(&message.queue))
+            .arg(reservation)
+            .arg(message_as_str);
+        invocation
+    }
+
+    /// Remove the workers whose last heartbeat expired by `now` from the
+    /// register.
+    fn expire_workers_invocation<'a>(
+        &self,
+        script: &'a redis::Script,
+        now: u64,
+    ) -> redis::ScriptInvocation<'a> {
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(&self.worker_expiry_sorted_set)
+            .key(&self.worker_register)
+            .arg(now);
+        invocation
+    }
+
+    fn delayed_queue_key(&self, queue: &str) -> String {
+        format!("{}_{}", self.delayed_queue_prefix, queue)
+    }
+
+    /// The sorted sets of delayed messages for `queue`, including the legacy
+    /// one for the default queue.
+    fn delayed_queue_keys(&self, queue: &str) -> Vec<String> {
+        let mut keys = vec![self.delayed_queue_key(queue)];
+        if queue == DEFAULT_QUEUE {
+            keys.push(self.legacy_delayed_queue.clone());
+        }
+        keys
+    }
+
+    fn processing_queue_key(&self, worker_id: &str) -> String {
+        format!("{}_{}", self.processing_queue_prefix, worker_id)
+    }
+
+    fn command_queue_key(&self, worker_id: &str) -> String {
+        format!("{}_{}", self.command_queue_prefix, worker_id)
+    }
+
+    fn result_notification_key(&self, signature_id: &str) -> String {
+        format!("{}_{}", self.result_notification_prefix, signature_id)
+    }
+
+    /// The set of signature IDs of the finished tasks in the header of a
+    /// chord.
+    fn chord_finished_key(&self, chord_id: &str) -> String {
+        format!("{}_{}", self.chord_finished_prefix, chord_id)
+    }
+
+    /// Set once the callback of a chord has been queued or failed.
+    fn chord_completed_key(&self, chord_id: &str) -> String {
+        format!("{}_{}", self.chord_completed_prefix, chord_id)
+    }
+
+    fn rate_limit_key(&self, task_id: &str) -> String {
+        format!("{}_{}", self.rate_limit_prefix, task_id)
+    }
+
+    fn dedup_key_key(&self, task_id: &str, dedup_key: &str) -> String {
+        format!("{}_{}_{}", self.dedup_key_prefix, task_id, dedup_key)
+    }
+}
+
 impl Broker for RedisBroker {
     fn push_message(&self, message: &crate::messages::Message) -> Result<()> {
         let mut con = self.redis_client.get_connection()?;
         let message_as_str = serde_json::to_string(&message)?;
-        con.lpush::<&str, String, ()>(&self.queue, message_as_str)?;
+        let mut pipe = redis::pipe();
+        self.queue_message(pipe.atomic(), message, &message_as_str, false);
+        pipe.exec(&mut con)?;
         Ok(())
     }
 
-    fn pop_message(&self) -> Result<Option<crate::messages::Message>> {
+    fn push_delayed_message(&self, message: &crate::messages::Message) -> Result<()> {
         let mut con = self.redis_client.get_connection()?;
-        let serialized_message: Option<String> = con.rpop(&self.queue, None)?;
-        match serialized_message {
-            Some(v) => Ok(Some(serde_json::from_str(&v)?)),
-            None => Ok(None),
+        con.zadd::<String, u64, String, ()>(
+            self.delayed_queue_key(&message.queue),
+            serde_json::to_string(&message)?,
+            due_score(message)?,
+        )?;
+        Ok(())
+    }
+
+    fn promote_due_messages(&self, queues: &[String]) -> Result<usize> {
+        let mut con = self.redis_client.get_connection()?;
+        let mut promoted = 0;
+        let script = redis::Script::new(PROMOTE_DUE_MESSAGES_SCRIPT);
+        for queue in queues {
+            for delayed_queue_key in self.delayed_queue_keys(queue) {
+                promoted += script
+                    .key(delayed_queue_key)
+                    .key(self.queue_notification_key(queue))
+                    .arg(unix_time_millis()?)
+                    .arg(MAX_PROMOTED_MESSAGES)
+                    .arg(format!("{}_{}", self.queue_prefix, queue))
+                    .arg(MAX_PRIORITY)
+                    .arg(DEFAULT_PRIORITY)
+                    .invoke::<usize>(&mut con)?;
+            }
         }
+        Ok(promoted)
+    }
+
+    fn reserve_message(
+        &self,
+        worker_id: &str,
+        queues: &[String],
+    ) -> Result<Option<crate::messages::Message>> {
+        let mut con = self.redis_client.get_connection()?;
+        let script = redis::Script::new(RESERVE_MESSAGE_SCRIPT);
+        let serialized_message: Option<String> = self
+            .reserve_message_invocation(&script, worker_id, queues)?
+            .invoke(&mut con)?;
+        self.reserved_message(&mut con, worker_id, serialized_message)
+    }
+
+    fn reserve_message_blocking(
+        &self,
+        worker_id: &str,
+        queues: &[String],
+        timeout: Duration,
+    ) -> Result<Option<crate::messages::Message>> {
+        if timeout.is_zero() {
+            // A zero timeout means blocking forever to Redis.
+            return Broker::reserve_message(self, worker_id, queues);
+        }
+
+        let mut con = self.redis_client.get_connection()?;
+
+        // Register the connection, so that pushing a command for the worker
+        // can unblock it. Don't block at all if a command arrived before the
+        // registration.
+        let client_id: i64 = redis::cmd("CLIENT").arg("ID").query(&mut con)?;
+        con.hset::<&str, &str, i64, ()>(&self.blocked_client_hash_map, worker_id, client_id)?;
+        let pending_commands: usize = con.llen(self.command_queue_key(worker_id))?;
+        let script = redis::Script::new(RESERVE_MESSAGE_SCRIPT);
+        let serialized_message: Option<String> = if pending_commands > 0 {
+            None
+        } else if let Some(message) = self
+            .reserve_message_invocation(&script, worker_id, queues)?
+            .invoke(&mut con)?
+        {
+            Some(message)
+        } else {
+            // Wait for a message to be queued on any of the queues, then try
+            // again. Another worker may have taken it in the meantime.
+            let notification_keys: Vec<String> = queues
+                .iter()
+                .map(|queue| self.queue_notification_key(queue))
+                .collect();
+            con.blpop::<Vec<String>, Option<(String, String)>>(
+                notification_keys,
+                blpop_timeout_seconds(timeout),
+            )?;
+            self.reserve_message_invocation(&script, worker_id, queues)?
+                .invoke(&mut con)?
+        };
+        con.hdel::<&str, &str, ()>(&self.blocked_client_hash_map, worker_id)?;
+
+        self.reserved_message(&mut con, worker_id, serialized_message)
+    }
+
+    fn ack_message(&self, worker_id: &str, message: &crate::messages::Message) -> Result<()> {
+        let mut con = self.redis_client.get_connection()?;
+        let message_as_str = serde_json::to_string(message)?;
+        redis::pipe()
+            .atomic()
+            .lrem(self.processing_queue_key(worker_id), 1, &message_as_str)
+            .zrem(
+                &self.reservation_sorted_set,
+                self.reservation_member(worker_id, &message_as_str),
+            )
+            .exec(&mut con)?;
+        Ok(())
+    }
+
+    fn nack_message(&self, worker_id: &str, message: &crate::messages::Message) -> Result<()> {
+        let mut con = self.redis_client.get_connection()?;
+        let message_as_str = serde_json::to_string(message)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .lrem(self.processing_queue_key(worker_id), 1, &message_as_str)
+            .zrem(
+                &self.reservation_sorted_set,
+                self.reservation_member(worker_id, &message_as_str),
+            );
+        self.queue_message(&mut pipe, message, &message_as_str, true);
+        pipe.exec(&mut con)?;
+        Ok(())
+    }
+
+    fn requeue_unacked_messages(&self, visibility_timeout: Duration) -> Result<usize> {
+        let mut con = self.redis_client.get_connection()?;
+        let now = unix_time_millis()?;
+        let expire_workers = redis::Script::new(EXPIRE_WORKERS_SCRIPT);
+        self.expire_workers_invocation(&expire_workers, now)
+            .invoke::<usize>(&mut con)?;
+
+        // Move the newest reservation first to the front of its queue, so
+        // that the oldest one ends up being the next to be picked up.
+        let reservations: Vec<(String, f64)> =
+            con.zrevrange_withscores(&self.reservation_sorted_set, 0, -1)?;
+        let requeue_reservation = redis::Script::new(REQUEUE_RESERVATION_SCRIPT);
+        let mut registered_workers = HashMap::new();
+        let mut requeued = 0;
+        for (reservation, reserved_at) in reservations {
+            let Some((worker_id, message_as_str)) = reservation.split_once(':') else {
+                continue;
+            };
+            let worker_registered = match registered_workers.get(worker_id) {
+                Some(registered) => *registered,
+                None => {
+                    let registered: bool = con.hexists(&self.worker_register, worker_id)?;
+                    registered_workers.insert(worker_id.to_string(), registered);
+                    registered
+                }
+            };
+            let held_for = Duration::from_millis(now.saturating_sub(reserved_at as u64));
+            if worker_registered && held_for <= visibility_timeout {
+                continue;
+            }
+
+            let message = match crate::messages::Message::from_json(message_as_str) {
+                Ok(message) => message,
+                Err(e) => {
+                    self.dead_letter(worker_id, message_as_str, &e)
+                        .exec(&mut con)?;
+                    continue;
+                }
+            };
+            let requeued_message = self
+                .requeue_reservation_invocation(
+                    &requeue_reservation,
+                    worker_id,
+                    &reservation,
+                    &message,
+                    message_as_str,
+                )
+                .invoke::<bool>(&mut con)?;
+            if requeued_message {
+                requeued += 1;
+            }
+        }
+        Ok(requeued)
     }
 
     fn store_result(&self, result_message: crate::messages::ResultMessage) -> Result<()> {
         let mut con = self.redis_client.get_connection()?;
-        con.hset::<&str, &str, String, ()>(
-            &self.result_hash_map,
-            &result_message.signature_id,
-            serde_json::to_string(&result_message)?,
+        let notification_key = self.result_notification_key(&result_message.signature_id);
+        redis::pipe()
+            .atomic()
+            .hset(
+                &self.result_hash_map,
+                &result_message.signature_id,
+                serde_json::to_string(&result_message)?,
+            )
+            .lpush(&notification_key, 1)
+            .expire(&notification_key, RESULT_NOTIFICATION_TTL_SECONDS)
+            .exec(&mut con)?;
+        Ok(())
+    }
+
+    fn add_finished_chord_task(&self, chord_id: &str, signature_id: &str) -> Result<usize> {
+        let mut con = self.redis_client.get_connection()?;
+        let finished_key = self.chord_finished_key(chord_id);
+        let (count,): (usize,) = redis::pipe()
+            .atomic()
+            .sadd(&finished_key, signature_id)
+            .ignore()
+            .scard(&finished_key)
+            .expire(&finished_key, CHORD_FINISHED_TTL_SECONDS)
+            .ignore()
+            .query(&mut con)?;
+        Ok(count)
+    }
+
+    fn is_chord_completed(&self, chord_id: &str) -> Result<bool> {
+        let mut con = self.redis_client.get_connection()?;
+        Ok(con.exists(self.chord_completed_key(chord_id))?)
+    }
+
+    fn set_chord_completed(&self, chord_id: &str) -> Result<()> {
+        let mut con = self.redis_client.get_connection()?;
+        con.set_ex::<String, i64, ()>(
+            self.chord_completed_key(chord_id),
+            1,
+            CHORD_FINISHED_TTL_SECONDS as u64,
         )?;
         Ok(())
     }
@@ -56,32 +736,170 @@ impl Broker for RedisBroker {
     fn get_result(&self, signature_id: &str) -> Result<Option<crate::messages::ResultMessage>> {
         let mut con = self.redis_client.get_connection()?;
         let serialized_result: Option<String> = con.hget(&self.result_hash_map, signature_id)?;
-        serialized_result.map_or(Ok(None), |v| {
-            serde_json::from_str(&v).map_err(|e| anyhow::anyhow!("{}", e))
-        })
+        serialized_result.map_or(Ok(None), |v| serde_json::from_str(&v).map_err(Error::from))
+    }
+
+    fn get_results(
+        &self,
+        signature_ids: &[String],
+    ) -> Result<Vec<Option<crate::messages::ResultMessage>>> {
+        if signature_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut con = self.redis_client.get_connection()?;
+        let serialized_results: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(&self.result_hash_map)
+            .arg(signature_ids)
+            .query(&mut con)?;
+        serialized_results
+            .into_iter()
+            .map(|serialized_result| {
+                serialized_result
+                    .map_or(Ok(None), |v| serde_json::from_str(&v).map_err(Error::from))
+            })
+            .collect()
+    }
+
+    fn wait_for_result(
+        &self,
+        signature_id: &str,
+        timeout: Duration,
+    ) -> Result<Option<crate::messages::ResultMessage>> {
+        let result = Broker::get_result(self, signature_id)?;
+        // A zero timeout would make BLPOP block forever.
+        if result.is_some() || timeout.is_zero() {
+            return Ok(result);
+        }
+
+        // Block until the notification pushed when the result is stored
+        // arrives, instead of polling the result hash.
+        let mut con = self.redis_client.get_connection()?;
+        let notification_key = self.result_notification_key(signature_id);
+        let notification: Option<(String, i64)> =
+            con.blpop(&notification_key, blpop_timeout_seconds(timeout))?;
+        if notification.is_none() {
+            return Ok(None);
+        }
+
+        // Put the notification back for anyone else waiting on the result.
+        redis::pipe()
+            .lpush(&notification_key, 1)
+            .expire(&notification_key, RESULT_NOTIFICATION_TTL_SECONDS)
+            .exec(&mut con)?;
+        Broker::get_result(self, signature_id)
+    }
+
+    fn forget_result(&self, signature_id: &str) -> Result<()> {
+        let mut con = self.redis_client.get_connection()?;
+        redis::pipe()
+            .hdel(&self.result_hash_map, signature_id)
+            .hdel(&self.task_info_hash_map, signature_id)
+            .del(self.result_notification_key(signature_id))
+            .exec(&mut con)?;
+        Ok(())
+    }
+
+    fn claim_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> Result<Option<String>> {
+        let mut con = self.redis_client.get_connection()?;
+        let holder = redis::Script::new(CLAIM_DEDUP_KEY_SCRIPT)
+            .key(self.dedup_key_key(task_id, dedup_key))
+            .arg(signature_id)
+            .arg(DEDUP_KEY_TTL_SECONDS)
+            .invoke(&mut con)?;
+        Ok(holder)
+    }
+
+    fn release_dedup_key(&self, task_id: &str, dedup_key: &str, signature_id: &str) -> Result<()> {
+        let mut con = self.redis_client.get_connection()?;
+        redis::Script::new(RELEASE_KEY_SCRIPT)
+            .key(self.dedup_key_key(task_id, dedup_key))
+            .arg(signature_id)
+            .invoke::<()>(&mut con)?;
+        Ok(())
+    }
+
+    fn take_rate_limit_token(
+        &self,
+        task_id: &str,
+        rate_limit: &RateLimit,
+    ) -> Result<Option<Duration>> {
+        let mut con = self.redis_client.get_connection()?;
+        let wait_millis: u64 = redis::Script::new(TAKE_RATE_LIMIT_TOKEN_SCRIPT)
+            .key(self.rate_limit_key(task_id))
+            .arg(rate_limit.max_runs.get())
+            .arg(rate_limit.per.as_millis() as u64)
+            .invoke(&mut con)?;
+        Ok((wait_millis > 0).then(|| Duration::from_millis(wait_millis)))
+    }
+
+    fn revoke(&self, signature_id: &str) -> Result<()> {
+        let mut con = self.redis_client.get_connection()?;
+        let now = unix_time_millis()?;
+        redis::pipe()
+            .atomic()
+            .zrembyscore(&self.revoked_sorted_set, "-inf", revoked_since(now))
+            .zadd(&self.revoked_sorted_set, signature_id, now)
+            .exec(&mut con)?;
+        Ok(())
+    }
+
+    fn is_revoked(&self, signature_id: &str) -> Result<bool> {
+        let mut con = self.redis_client.get_connection()?;
+        let revoked_at: Option<f64> = con.zscore(&self.revoked_sorted_set, signature_id)?;
+        let revoked_since = revoked_since(unix_time_millis()?);
+        Ok(revoked_at.is_some_and(|revoked_at| revoked_at as u64 > revoked_since))
     }
 
     fn push_command(&self, command: &crate::messages::Command, worker_id: &str) -> Result<()> {
         let mut con = self.redis_client.get_connection()?;
-        con.lpush::<&str, String, ()>(
-            &format!("{}_{}", self.command_queue_prefix, worker_id),
+        con.lpush::<String, String, ()>(
+            self.command_queue_key(worker_id),
             serde_json::to_string(&command)?,
         )?;
+
+        // Wake the worker up if it is blocked waiting for a message.
+        let blocked_client: Option<i64> = con.hget(&self.blocked_client_hash_map, worker_id)?;
+        if let Some(client_id) = blocked_client {
+            redis::cmd("CLIENT")
+                .arg("UNBLOCK")
+                .arg(client_id)
+                .exec(&mut con)?;
+        }
         Ok(())
     }
 
     fn pop_command(&self, worker_id: &str) -> Result<Option<crate::messages::Command>> {
         let mut con = self.redis_client.get_connection()?;
-        let serialized_command: Option<String> = con.rpop(
-            &format!("{}_{}", self.command_queue_prefix, worker_id),
-            None,
-        )?;
+        let serialized_command: Option<String> =
+            con.rpop(self.command_queue_key(worker_id), None)?;
         match serialized_command {
             Some(v) => Ok(Some(serde_json::from_str(&v)?)),
             None => Ok(None),
         }
     }
 
+    fn update_task_info(&self, info: TaskInfo) -> Result<()> {
+        let mut con = self.redis_client.get_connection()?;
+        con.hset::<&str, &str, String, ()>(
+            &self.task_info_hash_map,
+            &info.signature_id,
+            serde_json::to_string(&info)?,
+        )?;
+        Ok(())
+    }
+
+    fn get_task_info(&self, signature_id: &str) -> Result<Option<TaskInfo>> {
+        let mut con = self.redis_client.get_connection()?;
+        let serialized_info: Option<String> = con.hget(&self.task_info_hash_map, signature_id)?;
+        serialized_info.map_or(Ok(None), |v| serde_json::from_str(&v).map_err(Error::from))
+    }
+
     fn update_worker_info(&self, info: WorkerInfo) -> Result<()> {
         let mut con = self.redis_client.get_connection()?;
         con.hset::<&str, &str, String, ()>(
@@ -92,29 +910,476 @@ impl Broker for RedisBroker {
         Ok(())
     }
 
+    fn worker_heartbeat(&self, info: WorkerInfo, ttl: Duration) -> Result<()> {
+        let mut con = self.redis_client.get_connection()?;
+        let expires_at = unix_time_millis()? + ttl.as_millis() as u64;
+        redis::pipe()
+            .atomic()
+            .hset(
+                &self.worker_register,
+                &info.id,
+                serde_json::to_string(&info)?,
+            )
+            .zadd(&self.worker_expiry_sorted_set, &info.id, expires_at)
+            .exec(&mut con)?;
+        Ok(())
+    }
+
     fn get_worker_info(&self, worker_id: &str) -> Result<Option<WorkerInfo>> {
         let mut con = self.redis_client.get_connection()?;
         let serialized_info: Option<String> = con.hget(&self.worker_register, worker_id)?;
-        serialized_info.map_or(Ok(None), |v| {
-            serde_json::from_str(&v).map_err(|e| anyhow::anyhow!("{}", e))
-        })
+        serialized_info.map_or(Ok(None), |v| serde_json::from_str(&v).map_err(Error::from))
     }
 
     fn remove_worker_info(&self, worker_id: &str) -> Result<()> {
         let mut con = self.redis_client.get_connection()?;
-        con.hdel(&self.worker_register, worker_id)
-            .map_err(|e| anyhow::anyhow!("{}", e))
+        redis::pipe()
+            .atomic()
+            .hdel(&self.worker_register, worker_id)
+            .zrem(&self.worker_expiry_sorted_set, worker_id)
+            .exec(&mut con)?;
+        Ok(())
     }
 
     fn all_workers(&self) -> Result<Option<Vec<WorkerInfo>>> {
         let mut con = self.redis_client.get_connection()?;
+        let expire_workers = redis::Script::new(EXPIRE_WORKERS_SCRIPT);
+        self.expire_workers_invocation(&expire_workers, unix_time_millis()?)
+            .invoke::<usize>(&mut con)?;
 
         let serialized_info: Option<Vec<String>> = con.hvals(&self.worker_register)?;
         serialized_info.map_or(Ok(None), |info_vec| {
             info_vec
                 .iter()
-                .map(|v| serde_json::from_str(v).map_err(|e| anyhow::anyhow!("{}", e)))
+                .map(|v| serde_json::from_str(v).map_err(Error::from))
                 .collect()
         })
     }
+
+    fn acquire_scheduler_lock(&self, scheduler_id: &str, ttl: Duration) -> Result<bool> {
+        let mut con = self.redis_client.get_connection()?;
+        let acquired: i64 = redis::Script::new(ACQUIRE_SCHEDULER_LOCK_SCRIPT)
+            .key(&self.scheduler_lock)
+            .arg(scheduler_id)
+            .arg(ttl.as_millis() as u64)
+            .invoke(&mut con)?;
+        Ok(acquired == 1)
+    }
+
+    fn release_scheduler_lock(&self, scheduler_id: &str) -> Result<()> {
+        let mut con = self.redis_client.get_connection()?;
+        redis::Script::new(RELEASE_KEY_SCRIPT)
+            .key(&self.scheduler_lock)
+            .arg(scheduler_id)
+            .invoke::<()>(&mut con)?;
+        Ok(())
+    }
+
+    fn get_schedule_last_run(&self, name: &str) -> Result<Option<SystemTime>> {
+        let mut con = self.redis_client.get_connection()?;
+        let last_run: Option<u64> = con.hget(&self.schedule_last_run_hash_map, name)?;
+        Ok(last_run.map(|millis| UNIX_EPOCH + Duration::from_millis(millis)))
+    }
+
+    fn advance_schedule_last_run(
+        &self,
+        name: &str,
+        previous_run: Option<SystemTime>,
+        last_run: SystemTime,
+        scheduler_id: &str,
+    ) -> Result<bool> {
+        let mut con = self.redis_client.get_connection()?;
+        let previous_run = match previous_run {
+            Some(previous_run) => unix_millis(previous_run)?.to_string(),
+            None => String::new(),
+        };
+        let advanced: i64 = redis::Script::new(ADVANCE_SCHEDULE_LAST_RUN_SCRIPT)
+            .key(&self.scheduler_lock)
+            .key(&self.schedule_last_run_hash_map)
+            .arg(scheduler_id)
+            .arg(name)
+            .arg(previous_run)
+            .arg(unix_millis(last_run)?)
+            .invoke(&mut con)?;
+        Ok(advanced == 1)
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncBroker for RedisBroker {
+    async fn push_message(&self, message: &crate::messages::Message) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let message_as_str = serde_json::to_string(&message)?;
+        let mut pipe = redis::pipe();
+        self.queue_message(pipe.atomic(), message, &message_as_str, false);
+        pipe.exec_async(&mut con).await?;
+        Ok(())
+    }
+
+    async fn push_delayed_message(&self, message: &crate::messages::Message) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        con.zadd::<String, u64, String, ()>(
+            self.delayed_queue_key(&message.queue),
+            serde_json::to_string(&message)?,
+            due_score(message)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn promote_due_messages(&self, queues: &[String]) -> Result<usize> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let mut promoted = 0;
+        let script = redis::Script::new(PROMOTE_DUE_MESSAGES_SCRIPT);
+        for queue in queues {
+            for delayed_queue_key in self.delayed_queue_keys(queue) {
+                promoted += script
+                    .key(delayed_queue_key)
+                    .key(self.queue_notification_key(queue))
+                    .arg(unix_time_millis()?)
+                    .arg(MAX_PROMOTED_MESSAGES)
+                    .arg(format!("{}_{}", self.queue_prefix, queue))
+                    .arg(MAX_PRIORITY)
+                    .arg(DEFAULT_PRIORITY)
+                    .invoke_async::<usize>(&mut con)
+                    .await?;
+            }
+        }
+        Ok(promoted)
+    }
+
+    async fn reserve_message(
+        &self,
+        worker_id: &str,
+        queues: &[String],
+    ) -> Result<Option<crate::messages::Message>> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let script = redis::Script::new(RESERVE_MESSAGE_SCRIPT);
+        let serialized_message: Option<String> = self
+            .reserve_message_invocation(&script, worker_id, queues)?
+            .invoke_async(&mut con)
+            .await?;
+        let Some(stored) = serialized_message else {
+            return Ok(None);
+        };
+        let message = match crate::messages::Message::from_json(&stored) {
+            Ok(message) => message,
+            Err(e) => {
+                self.dead_letter(worker_id, &stored, &e)
+                    .exec_async(&mut con)
+                    .await?;
+                return Ok(None);
+            }
+        };
+        if let Some(pipe) = self.normalize_reservation(worker_id, &stored, &message)? {
+            pipe.exec_async(&mut con).await?;
+        }
+        Ok(Some(message))
+    }
+
+    async fn ack_message(&self, worker_id: &str, message: &crate::messages::Message) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let message_as_str = serde_json::to_string(message)?;
+        redis::pipe()
+            .atomic()
+            .lrem(self.processing_queue_key(worker_id), 1, &message_as_str)
+            .zrem(
+                &self.reservation_sorted_set,
+                self.reservation_member(worker_id, &message_as_str),
+            )
+            .exec_async(&mut con)
+            .await?;
+        Ok(())
+    }
+
+    async fn nack_message(
+        &self,
+        worker_id: &str,
+        message: &crate::messages::Message,
+    ) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let message_as_str = serde_json::to_string(message)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .lrem(self.processing_queue_key(worker_id), 1, &message_as_str)
+            .zrem(
+                &self.reservation_sorted_set,
+                self.reservation_member(worker_id, &message_as_str),
+            );
+        self.queue_message(&mut pipe, message, &message_as_str, true);
+        pipe.exec_async(&mut con).await?;
+        Ok(())
+    }
+
+    async fn requeue_unacked_messages(&self, visibility_timeout: Duration) -> Result<usize> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let now = unix_time_millis()?;
+        let expire_workers = redis::Script::new(EXPIRE_WORKERS_SCRIPT);
+        self.expire_workers_invocation(&expire_workers, now)
+            .invoke_async::<usize>(&mut con)
+            .await?;
+
+        let reservations: Vec<(String, f64)> = con
+            .zrevrange_withscores(&self.reservation_sorted_set, 0, -1)
+            .await?;
+        let requeue_reservation = redis::Script::new(REQUEUE_RESERVATION_SCRIPT);
+        let mut registered_workers = HashMap::new();
+        let mut requeued = 0;
+        for (reservation, reserved_at) in reservations {
+            let Some((worker_id, message_as_str)) = reservation.split_once(':') else {
+                continue;
+            };
+            let worker_registered = match registered_workers.get(worker_id) {
+                Some(registered) => *registered,
+                None => {
+                    let registered: bool = con.hexists(&self.worker_register, worker_id).await?;
+                    registered_workers.insert(worker_id.to_string(), registered);
+                    registered
+                }
+            };
+            let held_for = Duration::from_millis(now.saturating_sub(reserved_at as u64));
+            if worker_registered && held_for <= visibility_timeout {
+                continue;
+            }
+
+            let message = match crate::messages::Message::from_json(message_as_str) {
+                Ok(message) => message,
+                Err(e) => {
+                    self.dead_letter(worker_id, message_as_str, &e)
+                        .exec_async(&mut con)
+                        .await?;
+                    continue;
+                }
+            };
+            let requeued_message = self
+                .requeue_reservation_invocation(
+                    &requeue_reservation,
+                    worker_id,
+                    &reservation,
+                    &message,
+                    message_as_str,
+                )
+                .invoke_async::<bool>(&mut con)
+                .await?;
+            if requeued_message {
+                requeued += 1;
+            }
+        }
+        Ok(requeued)
+    }
+
+    async fn claim_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> Result<Option<String>> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let holder = redis::Script::new(CLAIM_DEDUP_KEY_SCRIPT)
+            .key(self.dedup_key_key(task_id, dedup_key))
+            .arg(signature_id)
+            .arg(DEDUP_KEY_TTL_SECONDS)
+            .invoke_async(&mut con)
+            .await?;
+        Ok(holder)
+    }
+
+    async fn release_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        redis::Script::new(RELEASE_KEY_SCRIPT)
+            .key(self.dedup_key_key(task_id, dedup_key))
+            .arg(signature_id)
+            .invoke_async::<()>(&mut con)
+            .await?;
+        Ok(())
+    }
+
+    async fn take_rate_limit_token(
+        &self,
+        task_id: &str,
+        rate_limit: &RateLimit,
+    ) -> Result<Option<Duration>> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let wait_millis: u64 = redis::Script::new(TAKE_RATE_LIMIT_TOKEN_SCRIPT)
+            .key(self.rate_limit_key(task_id))
+            .arg(rate_limit.max_runs.get())
+            .arg(rate_limit.per.as_millis() as u64)
+            .invoke_async(&mut con)
+            .await?;
+        Ok((wait_millis > 0).then(|| Duration::from_millis(wait_millis)))
+    }
+
+    async fn revoke(&self, signature_id: &str) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let now = unix_time_millis()?;
+        redis::pipe()
+            .atomic()
+            .zrembyscore(&self.revoked_sorted_set, "-inf", revoked_since(now))
+            .zadd(&self.revoked_sorted_set, signature_id, now)
+            .exec_async(&mut con)
+            .await?;
+        Ok(())
+    }
+
+    async fn is_revoked(&self, signature_id: &str) -> Result<bool> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let revoked_at: Option<f64> = con.zscore(&self.revoked_sorted_set, signature_id).await?;
+        let revoked_since = revoked_since(unix_time_millis()?);
+        Ok(revoked_at.is_some_and(|revoked_at| revoked_at as u64 > revoked_since))
+    }
+
+    async fn push_command(
+        &self,
+        command: &crate::messages::Command,
+        worker_id: &str,
+    ) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        con.lpush::<String, String, ()>(
+            self.command_queue_key(worker_id),
+            serde_json::to_string(&command)?,
+        )
+        .await?;
+
+        // Wake the worker up if it is a sync worker blocked waiting for a
+        // message.
+        let blocked_client: Option<i64> =
+            con.hget(&self.blocked_client_hash_map, worker_id).await?;
+        if let Some(client_id) = blocked_client {
+            redis::cmd("CLIENT")
+                .arg("UNBLOCK")
+                .arg(client_id)
+                .exec_async(&mut con)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn pop_command(&self, worker_id: &str) -> Result<Option<crate::messages::Command>> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let serialized_command: Option<String> =
+            con.rpop(self.command_queue_key(worker_id), None).await?;
+        match serialized_command {
+            Some(v) => Ok(Some(serde_json::from_str(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn store_result(&self, result_message: crate::messages::ResultMessage) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let notification_key = self.result_notification_key(&result_message.signature_id);
+        redis::pipe()
+            .atomic()
+            .hset(
+                &self.result_hash_map,
+                &result_message.signature_id,
+                serde_json::to_string(&result_message)?,
+            )
+            .lpush(&notification_key, 1)
+            .expire(&notification_key, RESULT_NOTIFICATION_TTL_SECONDS)
+            .exec_async(&mut con)
+            .await?;
+        Ok(())
+    }
+
+    async fn add_finished_chord_task(&self, chord_id: &str, signature_id: &str) -> Result<usize> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let finished_key = self.chord_finished_key(chord_id);
+        let (count,): (usize,) = redis::pipe()
+            .atomic()
+            .sadd(&finished_key, signature_id)
+            .ignore()
+            .scard(&finished_key)
+            .expire(&finished_key, CHORD_FINISHED_TTL_SECONDS)
+            .ignore()
+            .query_async(&mut con)
+            .await?;
+        Ok(count)
+    }
+
+    async fn is_chord_completed(&self, chord_id: &str) -> Result<bool> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        Ok(con.exists(self.chord_completed_key(chord_id)).await?)
+    }
+
+    async fn set_chord_completed(&self, chord_id: &str) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        con.set_ex::<String, i64, ()>(
+            self.chord_completed_key(chord_id),
+            1,
+            CHORD_FINISHED_TTL_SECONDS as u64,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_result(
+        &self,
+        signature_id: &str,
+    ) -> Result<Option<crate::messages::ResultMessage>> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let serialized_result: Option<String> =
+            con.hget(&self.result_hash_map, signature_id).await?;
+        serialized_result.map_or(Ok(None), |v| serde_json::from_str(&v).map_err(Error::from))
+    }
+
+    async fn update_task_info(&self, info: TaskInfo) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        con.hset::<&str, &str, String, ()>(
+            &self.task_info_hash_map,
+            &info.signature_id,
+            serde_json::to_string(&info)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_task_info(&self, signature_id: &str) -> Result<Option<TaskInfo>> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let serialized_info: Option<String> =
+            con.hget(&self.task_info_hash_map, signature_id).await?;
+        serialized_info.map_or(Ok(None), |v| serde_json::from_str(&v).map_err(Error::from))
+    }
+
+    async fn update_worker_info(&self, info: WorkerInfo) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        con.hset::<&str, &str, String, ()>(
+            &self.worker_register,
+            &info.id,
+            serde_json::to_string(&info)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn worker_heartbeat(&self, info: WorkerInfo, ttl: Duration) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        let expires_at = unix_time_millis()? + ttl.as_millis() as u64;
+        redis::pipe()
+            .atomic()
+            .hset(
+                &self.worker_register,
+                &info.id,
+                serde_json::to_string(&info)?,
+            )
+            .zadd(&self.worker_expiry_sorted_set, &info.id, expires_at)
+            .exec_async(&mut con)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_worker_info(&self, worker_id: &str) -> Result<()> {
+        let mut con = self.redis_client.get_multiplexed_async_connection().await?;
+        redis::pipe()
+            .atomic()
+            .hdel(&self.worker_register, worker_id)
+            .zrem(&self.worker_expiry_sorted_set, worker_id)
+            .exec_async(&mut con)
+            .await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,317 @@
+use std::iter;
+use std::marker::PhantomData;
+use std::time::SystemTime;
+
+use ulid::Ulid;
+
+use super::broker::{Broker, TaskInfo, TaskState};
+use super::error::{Error, Result};
+use super::messages::{ChainLink, Chord, Message, MAX_PRIORITY};
+use super::options::QueueOptions;
+use super::result::{AsyncResult, FailedTask, GroupResult};
+use super::task::{Signature, Task};
+use super::App;
+
+/// Builds an invocation of the task `T`, see `App::signature`.
+pub struct SignatureBuilder<B: Broker + 'static, T: Task> {
+    app: App<B>,
+    arg: T::ArgumentType,
+    options: QueueOptions,
+    link: Vec<ChainLink>,
+    link_error: Vec<ChainLink>,
+}
+
+impl<B: Broker + 'static, T: Task + 'static> SignatureBuilder<B, T> {
+    pub(crate) fn new(app: App<B>, arg: T::ArgumentType) -> Self {
+        Self {
+            app,
+            arg,
+            options: QueueOptions::default(),
+            link: Vec::new(),
+            link_error: Vec::new(),
+        }
+    }
+
+    /// Queue the task with `options`.
+    pub fn options(mut self, options: QueueOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Run the task `C` with the return value of the task, if it succeeds.
+    pub fn link<C>(mut self) -> Self
+    where
+        C: Task<ArgumentType = T::ReturnType> + 'static,
+    {
+        self.link.push(new_link::<C>());
+        self
+    }
+
+    /// Run the task `C` with the error of the task and its signature ID, if
+    /// it fails, panics or times out.
+    pub fn link_error<C>(mut self) -> Self
+    where
+        C: Task<ArgumentType = FailedTask<T::ErrorType>> + 'static,
+    {
+        self.link_error.push(new_link::<C>());
+        self
+    }
+
+    /// Queue the task for pickup by a worker. The worker that finishes it
+    /// queues the linked callbacks.
+    ///
+    /// Returns a handle to the result of the task invocation.
+    pub fn queue(self) -> Result<AsyncResult<T, B>> {
+        let unregistered = iter::once(T::ID)
+            .chain(self.link.iter().map(|link| link.task_id.as_str()))
+            .chain(self.link_error.iter().map(|link| link.task_id.as_str()))
+            .find(|task_id| !self.app.is_registered(task_id));
+        if let Some(task_id) = unregistered {
+            return Err(Error::NotRegistered(task_id.into()));
+        }
+
+        let dedup_key = self.options.dedup_key_or(T::dedup_key(&self.arg));
+        let mut message = task_message::<T>(self.arg, Ulid::new().to_string())?;
+        if let Some(dedup_key) = &dedup_key {
+            if let Some(signature_id) =
+                self.app
+                    .broker
+                    .claim_dedup_key(T::ID, dedup_key, &message.signature_id)?
+            {
+                // A duplicate is already queued or running.
+                return Ok(AsyncResult::new(self.app, signature_id));
+            }
+        }
+        message.dedup_key = dedup_key;
+        message.queue = self.options.queue_or(T::QUEUE);
+        message.priority = self.options.priority_or(T::PRIORITY);
+        message.not_before = self.options.not_before(message.queued_at);
+        message.expires_at = self.options.expires_at(message.queued_at);
+        message.link = self.link;
+        message.link_error = self.link_error;
+
+        let queued = self
+            .app
+            .set_task_state(&message, TaskState::Pending, None)
+            .and_then(|()| self.app.push_message(&message));
+        if let Err(error) = queued {
+            // Let the task be queued again, instead of holding the key until
+            // it lapses.
+            if let Some(dedup_key) = &message.dedup_key {
+                self.app
+                    .broker
+                    .release_dedup_key(T::ID, dedup_key, &message.signature_id)
+                    .unwrap_or_else(|_| {
+                        println!("Unable to release the dedup key '{dedup_key}'.");
+                    });
+            }
+            return Err(error);
+        }
+        Ok(AsyncResult::new(self.app, message.signature_id))
+    }
+}
+
+/// Builds a chain of tasks, see `App::chain`.
+pub struct ChainBuilder<B: Broker + 'static> {
+    app: App<B>,
+}
+
+impl<B: Broker + 'static> ChainBuilder<B> {
+    pub(crate) fn new(app: App<B>) -> Self {
+        Self { app }
+    }
+
+    /// Start the chain with the task `T`, invoked with `arg`.
+    pub fn then<T: Task + 'static>(self, arg: T::ArgumentType) -> Chain<B, T, T> {
+        Chain {
+            app: self.app,
+            arg,
+            links: Vec::new(),
+            tasks: PhantomData,
+        }
+    }
+}
+
+/// A chain of tasks starting with the task `F` and ending with the task `L`.
+///
+/// Each task after the first is run with the return value of the task before
+/// it as its argument, once that task has succeeded. If a task in the chain
+/// does not succeed, the tasks after it are never run, and fail with
+/// `TaskError::UpstreamFailed`.
+pub struct Chain<B: Broker + 'static, F: Task, L: Task> {
+    app: App<B>,
+    arg: F::ArgumentType,
+    links: Vec<ChainLink>,
+    tasks: PhantomData<(F, L)>,
+}
+
+impl<B: Broker + 'static, F: Task + 'static, L: Task + 'static> Chain<B, F, L> {
+    /// Add the task `T` to the end of the chain. It takes the return value of
+    /// the task currently at the end of the chain as its argument.
+    pub fn then<T>(mut self) -> Chain<B, F, T>
+    where
+        T: Task<ArgumentType = L::ReturnType> + 'static,
+    {
+        self.links.push(new_link::<T>());
+        Chain {
+            app: self.app,
+            arg: self.arg,
+            links: self.links,
+            tasks: PhantomData,
+        }
+    }
+
+    /// Queue the first task of the chain. The worker that finishes a task in
+    /// the chain queues the task after it.
+    ///
+    /// Returns a handle to the result of the last task in the chain.
+    pub fn queue(self) -> Result<AsyncResult<L, B>> {
+        let unregistered = iter::once(F::ID)
+            .chain(self.links.iter().map(|link| link.task_id.as_str()))
+            .find(|task_id| !self.app.is_registered(task_id));
+        if let Some(task_id) = unregistered {
+            return Err(Error::NotRegistered(task_id.into()));
+        }
+
+        let mut message = task_message::<F>(self.arg, Ulid::new().to_string())?;
+        message.chain = self.links;
+
+        self.app
+            .set_task_state(&message, TaskState::Pending, None)?;
+        for link in &message.chain {
+            set_link_pending(&self.app, link, message.queued_at)?;
+        }
+        self.app.push_message(&message)?;
+
+        let last_signature_id = message
+            .chain
+            .last()
+            .map_or(message.signature_id, |link| link.signature_id.clone());
+        Ok(AsyncResult::new(self.app, last_signature_id))
+    }
+}
+
+/// A group of invocations of the same task, see `App::group`.
+pub struct Group<B: Broker + 'static, A> {
+    app: App<B>,
+    args: Vec<A>,
+}
+
+impl<B: Broker + 'static, A> Group<B, A> {
+    pub(crate) fn new(app: App<B>, args: Vec<A>) -> Self {
+        Self { app, args }
+    }
+
+    /// Queue an invocation of the task `T` for each argument in the group.
+    ///
+    /// Returns a handle to the results of the invocations, in the order of
+    /// the arguments.
+    pub fn queue<T>(self) -> Result<GroupResult<T, B>>
+    where
+        T: Task<ArgumentType = A> + 'static,
+    {
+        let signature_ids = self
+            .args
+            .into_iter()
+            .map(|arg| {
+                self.app
+                    .queue_task::<T>(arg)
+                    .map(|async_result| async_result.id().to_string())
+            })
+            .collect::<Result<_>>()?;
+        Ok(GroupResult::new(self.app, signature_ids))
+    }
+
+    /// Queue the group as the header of a chord: an invocation of the task
+    /// `T` for each argument, and the callback task `C`, which is run with
+    /// all of their return values, in the order of the arguments, once the
+    /// last of them has finished.
+    ///
+    /// The worker that finishes the last task in the header queues the
+    /// callback. If any task in the header does not succeed, the callback is
+    /// never run, and fails with `TaskError::UpstreamFailed`.
+    ///
+    /// Returns a handle to the result of the callback.
+    pub fn chord<T, C>(self) -> Result<AsyncResult<C, B>>
+    where
+        T: Task<ArgumentType = A> + 'static,
+        C: Task<ArgumentType = Vec<T::ReturnType>> + 'static,
+    {
+        for task_id in [T::ID, C::ID] {
+            if !self.app.is_registered(task_id) {
+                return Err(Error::NotRegistered(task_id.into()));
+            }
+        }
+        if self.args.is_empty() {
+            // There is no header to wait for.
+            return self.app.queue_task::<C>(Vec::new());
+        }
+
+        let header_messages = self
+            .args
+            .into_iter()
+            .map(|arg| task_message::<T>(arg, Ulid::new().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        let chord = Chord {
+            id: Ulid::new().to_string(),
+            header: header_messages
+                .iter()
+                .map(|message| message.signature_id.clone())
+                .collect(),
+            callback: new_link::<C>(),
+        };
+
+        set_link_pending(&self.app, &chord.callback, SystemTime::now())?;
+        for mut message in header_messages {
+            message.chord = Some(chord.clone());
+            self.app
+                .set_task_state(&message, TaskState::Pending, None)?;
+            self.app.push_message(&message)?;
+        }
+        Ok(AsyncResult::new(
+            self.app,
+            chord.callback.signature_id.clone(),
+        ))
+    }
+}
+
+/// The message for invoking the task `T` with `arg`.
+fn task_message<T: Task>(arg: T::ArgumentType, signature_id: String) -> Result<Message> {
+    let signature = Signature::<T> {
+        arg,
+        id: signature_id.clone(),
+    };
+    let mut message = Message::new(
+        T::ID.into(),
+        signature_id,
+        serde_json::to_string(&signature)?,
+    );
+    message.queue = T::QUEUE.to_string();
+    message.priority = T::PRIORITY.min(MAX_PRIORITY);
+    Ok(message)
+}
+
+/// An invocation of the task `T` that is queued by a worker later on, once
+/// the tasks before it have finished.
+fn new_link<T: Task>() -> ChainLink {
+    ChainLink {
+        task_id: T::ID.to_string(),
+        signature_id: Ulid::new().to_string(),
+        queue: T::QUEUE.to_string(),
+        priority: T::PRIORITY.min(MAX_PRIORITY),
+    }
+}
+
+fn set_link_pending<B: Broker + 'static>(
+    app: &App<B>,
+    link: &ChainLink,
+    queued_at: SystemTime,
+) -> Result<()> {
+    app.broker.update_task_info(TaskInfo {
+        signature_id: link.signature_id.clone(),
+        state: TaskState::Pending,
+        worker_id: None,
+        queued_at,
+        updated_at: SystemTime::now(),
+    })
+}
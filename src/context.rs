@@ -0,0 +1,71 @@
+use std::cell::RefCell;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+thread_local! {
+    static CURRENT: RefCell<Option<TaskContext>> = const { RefCell::new(None) };
+}
+
+#[cfg(feature = "async")]
+tokio::task_local! {
+    static CURRENT_ASYNC: TaskContext;
+}
+
+/// The context of a running task invocation, available from within the
+/// `run` function of the task through `TaskContext::current`.
+#[derive(Clone, Debug)]
+pub struct TaskContext {
+    signature_id: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TaskContext {
+    pub(crate) fn new(signature_id: String) -> Self {
+        Self {
+            signature_id,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The context of the task invocation running on the current thread, or
+    /// in the current Tokio task for async tasks.
+    pub fn current() -> Option<Self> {
+        #[cfg(feature = "async")]
+        if let Ok(context) = CURRENT_ASYNC.try_with(Clone::clone) {
+            return Some(context);
+        }
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// The signature ID of the running task invocation.
+    pub fn signature_id(&self) -> &str {
+        &self.signature_id
+    }
+
+    /// Whether the task has been asked to stop, for instance because it hit
+    /// its soft time limit. Long running tasks should check this regularly,
+    /// and return early when it is set.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Run `f` with this as the context of the current thread.
+    pub(crate) fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let result = f();
+        CURRENT.with(|current| current.replace(previous));
+        result
+    }
+
+    /// Run `future` with this as the context of the current Tokio task.
+    #[cfg(feature = "async")]
+    pub(crate) async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT_ASYNC.scope(self, future).await
+    }
+}
@@ -0,0 +1,59 @@
+use thiserror::Error as ThisError;
+
+/// Errors returned by the apps, workers and brokers.
+#[derive(Debug, ThisError)]
+pub enum Error {
+    /// A worker received a message for a task it does not have registered.
+    #[error("Received message for unknown task ID '{0}'.")]
+    UnknownTask(String),
+    /// A task was queued without being registered with the app.
+    #[error("Can not queue task with ID '{0}' as it is not registered.")]
+    NotRegistered(String),
+    /// A message, result or task argument could not be (de)serialized.
+    #[error("Failed to (de)serialize: {0}")]
+    Serialization(#[from] serde_json::Error),
+    /// The broker failed. Holds the error of the underlying client, so
+    /// brokers can wrap any error of their own.
+    #[error("Broker error: {0}")]
+    Broker(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// A wait for the result of a task invocation timed out.
+    #[error("Timed out waiting for the result of task invocation '{0}'.")]
+    Timeout(String),
+    /// The task invocation was revoked before it finished.
+    #[error("Task invocation '{0}' was revoked.")]
+    Revoked(String),
+    /// A worker could not run a task to completion.
+    #[error("Failed to run task: {0}")]
+    TaskFailed(String),
+    /// A schedule for periodic tasks could not be parsed.
+    #[error("Invalid schedule: {0}")]
+    InvalidSchedule(String),
+    /// A rate limit for a task is out of range.
+    #[error("Invalid rate limit: {0}")]
+    InvalidRateLimit(String),
+    /// There was no message due to be run on the queue.
+    #[error("No message in the queue is due to run.")]
+    NoDueMessage,
+}
+
+impl Error {
+    /// Wrap an error from the client used by a broker.
+    pub fn broker(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Self::Broker(error.into())
+    }
+}
+
+impl From<redis::RedisError> for Error {
+    fn from(error: redis::RedisError) -> Self {
+        Self::broker(error)
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<tokio::task::JoinError> for Error {
+    fn from(error: tokio::task::JoinError) -> Self {
+        Self::TaskFailed(error.to_string())
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -1,68 +1,189 @@
+#[cfg(feature = "async")]
+pub mod async_app;
+#[cfg(feature = "async")]
+mod async_runner;
+#[cfg(feature = "async")]
+pub mod async_worker;
 pub mod broker;
 pub mod brokers;
+pub mod canvas;
+pub mod context;
+mod error;
 pub mod messages;
+pub mod options;
+pub mod result;
 mod runner;
+pub mod scheduler;
 pub mod task;
 pub mod worker;
 
-use broker::{Broker, WorkerInfo};
-use messages::{Command, Message, ResultMessage};
-use runner::TaskRunnerBuilder;
-use task::{Signature, Task};
+use broker::{Broker, TaskInfo, TaskState, WorkerInfo};
+use canvas::{ChainBuilder, Group, SignatureBuilder};
+use context::TaskContext;
+use messages::{Chord, Command, Message, ResultMessage, TaskOutcome};
+use options::QueueOptions;
+use result::AsyncResult;
+use runner::{ChordCompletion, TaskRunnerBuilder};
+use task::Task;
 
-use anyhow::{Context, Error};
-use serde_json;
+pub use error::{Error, Result};
 use std::collections::HashMap;
-use ulid::Ulid;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-pub struct App<'a, B: Broker> {
-    task_runner_builders: HashMap<String, TaskRunnerBuilder<B>>,
-    broker: &'a B,
+/// The registered tasks and the broker used to queue and run them.
+///
+/// The app owns its broker, and cloning it is cheap, so it can be shared
+/// between threads, for instance by a server queueing tasks and a pool of
+/// workers running them.
+pub struct App<B: Broker> {
+    task_runner_builders: Arc<HashMap<String, TaskRunnerBuilder<B>>>,
+    broker: Arc<B>,
 }
 
-impl<'a, B: Broker + 'static> App<'a, B> {
-    pub fn new(broker: &'a B) -> Self {
+impl<B: Broker> Clone for App<B> {
+    fn clone(&self) -> Self {
         Self {
-            task_runner_builders: HashMap::new(),
+            task_runner_builders: self.task_runner_builders.clone(),
+            broker: self.broker.clone(),
+        }
+    }
+}
+
+impl<B: Broker + 'static> App<B> {
+    pub fn new(broker: B) -> Self {
+        Self::with_shared_broker(Arc::new(broker))
+    }
+
+    /// Create an app using a broker that is also used elsewhere.
+    pub fn with_shared_broker(broker: Arc<B>) -> Self {
+        Self {
+            task_runner_builders: Arc::new(HashMap::new()),
             broker,
         }
     }
 
+    /// Register a task, so that it can be queued and run through the app.
+    ///
+    /// Clones of the app made before the task was registered do not know of
+    /// it, so all tasks should be registered before the app is shared.
     pub fn register_task<T: Task + 'static>(&mut self) {
-        self.task_runner_builders
-            .insert(T::ID.into(), Box::new(runner::build_task_runner::<T, B>));
+        Arc::make_mut(&mut self.task_runner_builders)
+            .insert(T::ID.into(), Arc::new(runner::build_task_runner::<T, B>));
     }
 
     /// Queue a task for pickup by a worker.
     ///
-    /// Returns the signature_id for the task invocation, which can be used to
-    /// lookup the result of running the task.
-    pub fn queue_task<T: Task + 'static>(&self, arg: T::ArgumentType) -> Result<String, Error> {
-        if !self.task_runner_builders.contains_key(T::ID) {
-            anyhow::bail!(
-                "Can not queue task with ID '{}' as it is not registered.",
-                T::ID
-            );
-        }
+    /// Returns a handle to the result of the task invocation.
+    pub fn queue_task<T: Task + 'static>(
+        &self,
+        arg: T::ArgumentType,
+    ) -> Result<AsyncResult<T, B>, Error> {
+        self.queue_task_with::<T>(arg, QueueOptions::default())
+    }
 
-        let signature_id = Ulid::new().to_string();
-        let signature = Signature::<T> {
-            arg,
-            id: signature_id.clone(),
-        };
-        self.broker
-            .push_message(&Message {
-                task_id: T::ID.into(),
-                signature: serde_json::to_string(&signature)?,
-            })
-            .context("Failed to put task invocation on the queue.")?;
-        Ok(signature_id)
+    /// Queue a task for pickup by a worker, with options for when it is run.
+    ///
+    /// Returns a handle to the result of the task invocation.
+    pub fn queue_task_with<T: Task + 'static>(
+        &self,
+        arg: T::ArgumentType,
+        options: QueueOptions,
+    ) -> Result<AsyncResult<T, B>, Error> {
+        self.signature::<T>(arg).options(options).queue()
+    }
+
+    /// Start building a task invocation, to attach callbacks to before it is
+    /// queued.
+    pub fn signature<T: Task + 'static>(&self, arg: T::ArgumentType) -> SignatureBuilder<B, T> {
+        SignatureBuilder::new(self.clone(), arg)
+    }
+
+    /// Start building a chain of tasks, each run with the return value of the
+    /// task before it as its argument.
+    pub fn chain(&self) -> ChainBuilder<B> {
+        ChainBuilder::new(self.clone())
+    }
+
+    /// Start building a group of invocations of the same task, one for each
+    /// of `args`.
+    pub fn group<A>(&self, args: Vec<A>) -> Group<B, A> {
+        Group::new(self.clone(), args)
+    }
+
+    /// Get a handle to the result of an earlier queued task invocation.
+    pub fn async_result<T: Task + 'static>(&self, signature_id: String) -> AsyncResult<T, B> {
+        AsyncResult::new(self.clone(), signature_id)
     }
 
     pub fn get_task_result(&self, signatrue_id: &str) -> Result<Option<ResultMessage>, Error> {
         self.broker.get_result(signatrue_id)
     }
 
+    /// Get the results of several task invocations at once, in the order of
+    /// `signature_ids`.
+    pub fn get_task_results(
+        &self,
+        signature_ids: &[String],
+    ) -> Result<Vec<Option<ResultMessage>>, Error> {
+        self.broker.get_results(signature_ids)
+    }
+
+    /// Wait up to `timeout` for the result of a task invocation.
+    ///
+    /// Returns `None` if no result was stored within the timeout.
+    pub fn wait_for_result(
+        &self,
+        signature_id: &str,
+        timeout: Duration,
+    ) -> Result<Option<ResultMessage>, Error> {
+        self.broker.wait_for_result(signature_id, timeout)
+    }
+
+    /// Remove the stored result and state of a task invocation.
+    pub fn forget_task_result(&self, signature_id: &str) -> Result<(), Error> {
+        self.broker.forget_result(signature_id)
+    }
+
+    /// Get the current state of a task invocation, along with which worker
+    /// handled it and when.
+    pub fn get_task_state(&self, signature_id: &str) -> Result<Option<TaskInfo>, Error> {
+        self.broker.get_task_info(signature_id)
+    }
+
+    /// Put messages held by workers that died, or have held them for longer
+    /// than `visibility_timeout`, back on the queue.
+    ///
+    /// Workers do this themselves while idle, but it can also be run from a
+    /// separate process. Returns the number of messages put back.
+    pub fn requeue_unacked_messages(&self, visibility_timeout: Duration) -> Result<usize, Error> {
+        self.broker.requeue_unacked_messages(visibility_timeout)
+    }
+
+    /// Revoke a task invocation, so that workers skip it if they have not
+    /// started on it yet.
+    ///
+    /// With `terminate`, the worker running the task is also told to stop it,
+    /// by cancelling its `TaskContext`. The task then has to return early by
+    /// itself. Either way, the task ends up in the `Revoked` state.
+    pub fn revoke(&self, signature_id: &str, terminate: bool) -> Result<(), Error> {
+        self.broker.revoke(signature_id)?;
+        if !terminate {
+            return Ok(());
+        }
+
+        let running_on = self
+            .broker
+            .get_task_info(signature_id)?
+            .filter(|info| matches!(info.state, TaskState::Received | TaskState::Started))
+            .and_then(|info| info.worker_id);
+        if let Some(worker_id) = running_on {
+            self.broker
+                .push_command(&Command::TerminateTask(signature_id.into()), &worker_id)?;
+        }
+        Ok(())
+    }
+
     pub fn queue_command(&self, command: &Command, worker_id: &str) -> Result<(), Error> {
         self.broker.push_command(command, worker_id)
     }
@@ -75,24 +196,168 @@ impl<'a, B: Broker + 'static> App<'a, B> {
         self.broker.all_workers()
     }
 
-    fn handle_message(&self, message: &Message) -> Result<(), Error> {
+    fn handle_message(
+        &self,
+        message: &Message,
+        worker_id: &str,
+        context: &TaskContext,
+    ) -> Result<(), Error> {
+        if message.is_expired() {
+            return self.store_task_outcome(message, TaskOutcome::Expired, worker_id);
+        }
+
         let task_runner = match self.task_runner_builders.get(&message.task_id) {
-            Some(task_runner_builder) => Ok(task_runner_builder(&message.signature)?),
-            None => Err(anyhow::anyhow!(
-                "Received message for unknown task ID '{}'.",
-                &message.task_id
-            )),
-        }?;
+            Some(task_runner_builder) => task_runner_builder(message),
+            None => Err(Error::UnknownTask(message.task_id.clone())),
+        };
+        let task_runner = match task_runner {
+            Ok(task_runner) => task_runner,
+            Err(error) => {
+                // Redelivering the message would fail the same way.
+                let outcome = TaskOutcome::Rejected(error.to_string());
+                return self.store_task_outcome(message, outcome, worker_id);
+            }
+        };
 
-        task_runner.run_task(self)?;
+        task_runner.run_task(self, worker_id, context)?;
 
         Ok(())
     }
 
+    fn is_registered(&self, task_id: &str) -> bool {
+        self.task_runner_builders.contains_key(task_id)
+    }
+
+    fn is_revoked(&self, signature_id: &str) -> Result<bool, Error> {
+        self.broker.is_revoked(signature_id)
+    }
+
+    /// Acknowledge a reserved message that the worker failed to handle,
+    /// putting it back on the queue to be handled again, or rejecting it if
+    /// it has been redelivered too many times already.
+    fn redeliver_message(
+        &self,
+        message: &Message,
+        worker_id: &str,
+        error: &Error,
+    ) -> Result<(), Error> {
+        match runner::redelivered_message(message) {
+            Some(redelivered_message) => self.requeue_message(&redelivered_message)?,
+            None => {
+                let outcome = TaskOutcome::Rejected(error.to_string());
+                self.store_task_outcome(message, outcome, worker_id)?
+            }
+        }
+        self.broker.ack_message(worker_id, message)
+    }
+
+    /// Put a message back on the queue, for instance to retry a task or
+    /// because it was picked up before it was due.
+    fn requeue_message(&self, message: &Message) -> Result<(), Error> {
+        self.push_message(message)
+    }
+
+    /// Push a message to the queue, or to the delayed messages if it is not
+    /// due yet.
+    fn push_message(&self, message: &Message) -> Result<(), Error> {
+        if message.is_due() {
+            self.broker.push_message(message)
+        } else {
+            self.broker.push_delayed_message(message)
+        }
+    }
+
+    fn set_task_state(
+        &self,
+        message: &Message,
+        state: TaskState,
+        worker_id: Option<&str>,
+    ) -> Result<(), Error> {
+        self.broker.update_task_info(TaskInfo {
+            signature_id: message.signature_id.clone(),
+            state,
+            worker_id: worker_id.map(str::to_string),
+            queued_at: message.queued_at,
+            updated_at: SystemTime::now(),
+        })
+    }
+
     fn store_task_result(&self, result: ResultMessage) -> Result<(), Error> {
         self.broker.store_result(result)
     }
 
+    /// Store the outcome of a task invocation, and move it to the matching
+    /// final state.
+    ///
+    /// Any success or error callbacks linked to the task are queued. If the
+    /// task is part of a chain, the next task in the chain is queued
+    /// when it succeeded, and the rest of the chain is failed otherwise. If
+    /// it is the last task in the header of a chord to finish, the callback
+    /// of the chord is queued or failed.
+    fn store_task_outcome(
+        &self,
+        message: &Message,
+        outcome: TaskOutcome,
+        worker_id: &str,
+    ) -> Result<(), Error> {
+        let state = runner::final_state(&outcome);
+        let callback_messages = runner::callback_messages(message, &outcome)?;
+        let next_message = match &outcome {
+            TaskOutcome::Success(value) => runner::next_in_chain(message, value)?,
+            _ => {
+                for (result, info) in runner::upstream_failures(message) {
+                    self.store_task_result(result)?;
+                    self.broker.update_task_info(info)?;
+                }
+                None
+            }
+        };
+
+        self.store_task_result(ResultMessage {
+            signature_id: message.signature_id.clone(),
+            outcome,
+        })?;
+        self.set_task_state(message, state, Some(worker_id))?;
+        if let Some(dedup_key) = &message.dedup_key {
+            self.broker
+                .release_dedup_key(&message.task_id, dedup_key, &message.signature_id)?;
+        }
+
+        for next_message in next_message.iter().chain(&callback_messages) {
+            self.set_task_state(next_message, TaskState::Pending, None)?;
+            self.push_message(next_message)?;
+        }
+        if let Some(chord) = &message.chord {
+            // The result is stored before counting the task as finished, so
+            // whoever counts the last one sees the results of all of them.
+            // Completing is only recorded once done, so that a redelivered
+            // message completes the chord if completing it failed before.
+            let finished = self
+                .broker
+                .add_finished_chord_task(&chord.id, &message.signature_id)?;
+            if finished == chord.header.len() && !self.broker.is_chord_completed(&chord.id)? {
+                self.complete_chord(chord, message.queued_at)?;
+                self.broker.set_chord_completed(&chord.id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue or fail the callback of a chord whose header has finished.
+    fn complete_chord(&self, chord: &Chord, queued_at: SystemTime) -> Result<(), Error> {
+        let results = self.broker.get_results(&chord.header)?;
+        match runner::complete_chord(chord, results, queued_at)? {
+            ChordCompletion::Queue(callback_message) => {
+                self.set_task_state(&callback_message, TaskState::Pending, None)?;
+                self.push_message(&callback_message)
+            }
+            ChordCompletion::Fail(result, info) => {
+                self.store_task_result(result)?;
+                self.broker.update_task_info(info)
+            }
+        }
+    }
+
     fn update_worker_info(&self, info: WorkerInfo) -> Result<(), Error> {
         self.broker.update_worker_info(info)
     }
@@ -1,18 +1,229 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::SystemTime;
+use ulid::Ulid;
 
+/// The queue tasks are sent to unless they declare or are queued with
+/// another one.
+pub const DEFAULT_QUEUE: &str = "default";
+
+/// The priority of messages unless their task declares or they are queued
+/// with another one.
+pub const DEFAULT_PRIORITY: u8 = 0;
+
+/// The highest priority a message can have. Higher priorities are treated as
+/// this one.
+pub const MAX_PRIORITY: u8 = 9;
+
+/// A task invocation sent to the queue.
+///
+/// Fields added since the first release default to what `Message::new`
+/// sets them to, so that messages queued by older versions can still be run.
+/// Deserialize stored messages with `Message::from_json`, which also fills in
+/// the signature ID of the ones queued before it was part of the message.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Message {
     pub task_id: String,
+    /// Empty when deserialized from a message queued by the first release,
+    /// unless deserialized with `Message::from_json`.
+    #[serde(default)]
+    pub signature_id: String,
     pub signature: String,
+    /// The name of the queue the message is sent to.
+    #[serde(default = "default_queue")]
+    pub queue: String,
+    /// Messages with a higher priority are taken off the queue first.
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+    #[serde(default = "SystemTime::now")]
+    pub queued_at: SystemTime,
+    /// Which attempt at running the task this message is for, starting at 1.
+    #[serde(default = "first_attempt")]
+    pub attempt: u32,
+    /// How many times the message was put back on the queue because a worker
+    /// failed to handle it.
+    #[serde(default)]
+    pub redeliveries: u32,
+    /// The task must not be run before this time.
+    #[serde(default)]
+    pub not_before: Option<SystemTime>,
+    /// The task is not run if it is picked up after this time.
+    #[serde(default)]
+    pub expires_at: Option<SystemTime>,
+    /// The tasks to run after this one, each with the return value of the one
+    /// before it as its argument.
+    #[serde(default)]
+    pub chain: Vec<ChainLink>,
+    /// The chord this task is in the header of, if any.
+    #[serde(default)]
+    pub chord: Option<Chord>,
+    /// The tasks to run with the return value of this one, if it succeeds.
+    #[serde(default, deserialize_with = "deserialize_links")]
+    pub link: Vec<ChainLink>,
+    /// The tasks to run with a `FailedTask` holding the error of this one, if
+    /// it fails.
+    #[serde(default, deserialize_with = "deserialize_links")]
+    pub link_error: Vec<ChainLink>,
+    /// The key keeping duplicates of the task from being queued until it has
+    /// finished, if any.
+    #[serde(default)]
+    pub dedup_key: Option<String>,
+}
+
+/// A task queued by a worker once the task(s) before it have finished, such
+/// as the next task in a chain or a callback.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChainLink {
+    pub task_id: String,
+    pub signature_id: String,
+    /// The name of the queue the task is sent to.
+    #[serde(default = "default_queue")]
+    pub queue: String,
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+}
+
+impl ChainLink {
+    /// A link to the task `task_id` on the default queue, with a new
+    /// signature ID.
+    fn from_task_id(task_id: String) -> Self {
+        Self {
+            task_id,
+            signature_id: Ulid::new().to_string(),
+            queue: default_queue(),
+            priority: default_priority(),
+        }
+    }
+}
+
+/// A linked callback as stored in a message. Links used to be just the ID of
+/// the task, with the signature ID made up when the callback was queued.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredLink {
+    TaskId(String),
+    Link(ChainLink),
+}
+
+/// The ID embedded in a serialized signature.
+#[derive(Deserialize)]
+struct EmbeddedSignatureId {
+    id: String,
+}
+
+fn deserialize_links<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ChainLink>, D::Error> {
+    Ok(Vec::<StoredLink>::deserialize(deserializer)?
+        .into_iter()
+        .map(|link| match link {
+            StoredLink::TaskId(task_id) => ChainLink::from_task_id(task_id),
+            StoredLink::Link(link) => link,
+        })
+        .collect())
+}
+
+fn default_queue() -> String {
+    DEFAULT_QUEUE.to_string()
+}
+
+fn default_priority() -> u8 {
+    DEFAULT_PRIORITY
+}
+
+fn first_attempt() -> u32 {
+    1
+}
+
+/// A group of tasks, the header, with a callback that is queued with all of
+/// their return values once the last of them has finished.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Chord {
+    pub id: String,
+    /// The signature IDs of the tasks in the header, in the order their
+    /// return values are passed to the callback.
+    pub header: Vec<String>,
+    pub callback: ChainLink,
+}
+
+impl Message {
+    pub fn new(task_id: String, signature_id: String, signature: String) -> Self {
+        Self {
+            task_id,
+            signature_id,
+            signature,
+            queue: default_queue(),
+            priority: default_priority(),
+            queued_at: SystemTime::now(),
+            attempt: first_attempt(),
+            redeliveries: 0,
+            not_before: None,
+            expires_at: None,
+            chain: Vec::new(),
+            chord: None,
+            link: Vec::new(),
+            link_error: Vec::new(),
+            dedup_key: None,
+        }
+    }
+
+    /// Deserialize a message queued by any version. Messages queued by the
+    /// first release only have the signature ID embedded in the signature.
+    pub fn from_json(message_as_str: &str) -> serde_json::Result<Self> {
+        let mut message: Self = serde_json::from_str(message_as_str)?;
+        if message.signature_id.is_empty() {
+            message.signature_id =
+                serde_json::from_str::<EmbeddedSignatureId>(&message.signature)?.id;
+        }
+        Ok(message)
+    }
+
+    /// Whether the task may be run now.
+    pub fn is_due(&self) -> bool {
+        self.not_before
+            .is_none_or(|not_before| not_before <= SystemTime::now())
+    }
+
+    /// Whether the task was picked up too late, and should not be run.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at < SystemTime::now())
+    }
+}
+
+/// The outcome of running a task, as stored in the result backend.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum TaskOutcome {
+    /// The task succeeded. Holds the serialized return value.
+    Success(String),
+    /// The task returned an error. Holds the serialized error.
+    Failure(String),
+    /// The task panicked. Holds the panic message.
+    Panic(String),
+    /// The task hit its hard time limit, and the worker gave up on it.
+    TimedOut,
+    /// The task expired before a worker picked it up, and was not run.
+    Expired,
+    /// The task was revoked, and either not run or terminated while running.
+    Revoked,
+    /// The task was not run, because a task before it in its chain, or in
+    /// the header of its chord, did not succeed. Holds the signature ID of
+    /// that task.
+    UpstreamFailed(String),
+    /// The message could not be handled, such as for an unknown task or with
+    /// a signature that does not deserialize, and was given up on. Holds why.
+    Rejected(String),
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ResultMessage {
     pub signature_id: String,
-    pub result: String,
+    pub outcome: TaskOutcome,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum Command {
     StopWorker,
+    /// Ask the task invocation with the given signature ID to stop, if the
+    /// worker is running it.
+    TerminateTask(String),
 }
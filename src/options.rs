@@ -0,0 +1,98 @@
+use super::messages::MAX_PRIORITY;
+use std::time::{Duration, SystemTime};
+
+/// Options for queueing a task invocation, see `App::queue_task_with`.
+#[derive(Clone, Debug, Default)]
+pub struct QueueOptions {
+    delay: Option<Delay>,
+    expires: Option<Duration>,
+    queue: Option<String>,
+    priority: Option<u8>,
+    dedup_key: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Delay {
+    Countdown(Duration),
+    Eta(SystemTime),
+}
+
+impl QueueOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the task no sooner than `countdown` after it is queued. Replaces
+    /// any earlier set ETA.
+    pub fn countdown(mut self, countdown: Duration) -> Self {
+        self.delay = Some(Delay::Countdown(countdown));
+        self
+    }
+
+    /// Run the task no sooner than `eta`. Replaces any earlier set countdown.
+    pub fn eta(mut self, eta: SystemTime) -> Self {
+        self.delay = Some(Delay::Eta(eta));
+        self
+    }
+
+    /// Do not run the task if it is picked up by a worker more than `expires`
+    /// after it is queued. The task is marked as revoked instead.
+    pub fn expires(mut self, expires: Duration) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Send the task to the queue named `queue`, instead of the queue the
+    /// task declares.
+    pub fn queue(mut self, queue: &str) -> Self {
+        self.queue = Some(queue.to_string());
+        self
+    }
+
+    /// Queue the task with `priority`, from 0 up to `MAX_PRIORITY`, instead of
+    /// the priority the task declares. Higher priority tasks are run first.
+    /// Higher priorities are lowered to `MAX_PRIORITY`.
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = Some(priority.min(MAX_PRIORITY));
+        self
+    }
+
+    /// Deduplicate the task by `dedup_key`, instead of the key the task
+    /// derives from its argument. See `Task::dedup_key`.
+    pub fn dedup_key(mut self, dedup_key: &str) -> Self {
+        self.dedup_key = Some(dedup_key.to_string());
+        self
+    }
+
+    /// The name of the queue to send a task declaring `task_queue` to.
+    pub(crate) fn queue_or(&self, task_queue: &str) -> String {
+        self.queue.as_deref().unwrap_or(task_queue).to_string()
+    }
+
+    /// The key to deduplicate a task deriving `task_dedup_key` by.
+    pub(crate) fn dedup_key_or(&self, task_dedup_key: Option<String>) -> Option<String> {
+        self.dedup_key.clone().or(task_dedup_key)
+    }
+
+    /// The priority to queue a task declaring `task_priority` with, at most
+    /// `MAX_PRIORITY`.
+    pub(crate) fn priority_or(&self, task_priority: u8) -> u8 {
+        self.priority.unwrap_or(task_priority).min(MAX_PRIORITY)
+    }
+
+    /// The time the task must not be run before, if any, when queued at
+    /// `queued_at`.
+    pub(crate) fn not_before(&self, queued_at: SystemTime) -> Option<SystemTime> {
+        match self.delay {
+            None => None,
+            Some(Delay::Countdown(countdown)) => Some(queued_at + countdown),
+            Some(Delay::Eta(eta)) => Some(eta),
+        }
+    }
+
+    /// The time after which the task should no longer be run, if any, when
+    /// queued at `queued_at`.
+    pub(crate) fn expires_at(&self, queued_at: SystemTime) -> Option<SystemTime> {
+        self.expires.map(|expires| queued_at + expires)
+    }
+}
@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use super::broker::{Broker, TaskInfo};
+use super::error::{Error, Result};
+use super::messages::{ResultMessage, TaskOutcome};
+use super::task::Task;
+use super::App;
+
+/// Why a task invocation did not produce a return value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TaskError<E> {
+    /// The task returned an error.
+    Failed(E),
+    /// The task panicked. Holds the panic message.
+    Panicked(String),
+    /// The task hit its hard time limit.
+    TimedOut,
+    /// The task was not run, because a task before it in its chain, or in
+    /// the header of its chord, did not succeed. Holds the signature ID of
+    /// that task.
+    UpstreamFailed(String),
+    /// The worker could not handle the task invocation, and gave up on it
+    /// without running the task. Holds why.
+    Rejected(String),
+}
+
+/// The argument of an error callback, see `SignatureBuilder::link_error`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FailedTask<E> {
+    /// The signature ID of the task invocation that failed.
+    pub signature_id: String,
+    pub error: TaskError<E>,
+}
+
+/// The result of a finished task invocation.
+pub type TaskResult<T> =
+    std::result::Result<<T as Task>::ReturnType, TaskError<<T as Task>::ErrorType>>;
+
+/// A handle to the result of a queued task invocation.
+///
+/// The stored result is deserialized into the return and error types of the
+/// task `T`, so the result can not be mistaken for that of another task.
+pub struct AsyncResult<T: Task, B: Broker + 'static> {
+    app: App<B>,
+    signature_id: String,
+    task: PhantomData<T>,
+}
+
+impl<T: Task, B: Broker + 'static> AsyncResult<T, B> {
+    pub(crate) fn new(app: App<B>, signature_id: String) -> Self {
+        Self {
+            app,
+            signature_id,
+            task: PhantomData,
+        }
+    }
+
+    /// The signature ID of the task invocation.
+    pub fn id(&self) -> &str {
+        &self.signature_id
+    }
+
+    pub fn state(&self) -> Result<Option<TaskInfo>> {
+        self.app.get_task_state(&self.signature_id)
+    }
+
+    /// Get the result of the task, if it has finished.
+    ///
+    /// Returns `Error::Revoked` if the task was revoked or expired.
+    pub fn try_get(&self) -> Result<Option<TaskResult<T>>> {
+        self.app
+            .get_task_result(&self.signature_id)?
+            .map(deserialize_result::<T>)
+            .transpose()
+    }
+
+    /// Wait for the task to finish and get its result.
+    ///
+    /// Returns `Error::Timeout` if the task has not finished within `timeout`,
+    /// and `Error::Revoked` if it was revoked or expired.
+    pub fn wait(&self, timeout: Duration) -> Result<TaskResult<T>> {
+        match self.app.wait_for_result(&self.signature_id, timeout)? {
+            Some(result_message) => deserialize_result::<T>(result_message),
+            None => Err(Error::Timeout(self.signature_id.clone())),
+        }
+    }
+
+    /// Remove the result and state of the task invocation from the result
+    /// backend.
+    pub fn forget(self) -> Result<()> {
+        self.app.forget_task_result(&self.signature_id)
+    }
+}
+
+/// A handle to the results of a group of invocations of the task `T`, see
+/// `App::group`.
+pub struct GroupResult<T: Task, B: Broker + 'static> {
+    app: App<B>,
+    signature_ids: Vec<String>,
+    task: PhantomData<T>,
+}
+
+impl<T: Task, B: Broker + 'static> GroupResult<T, B> {
+    pub(crate) fn new(app: App<B>, signature_ids: Vec<String>) -> Self {
+        Self {
+            app,
+            signature_ids,
+            task: PhantomData,
+        }
+    }
+
+    /// The signature IDs of the task invocations in the group.
+    pub fn ids(&self) -> &[String] {
+        &self.signature_ids
+    }
+
+    /// The number of task invocations in the group.
+    pub fn len(&self) -> usize {
+        self.signature_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signature_ids.is_empty()
+    }
+
+    /// The number of task invocations in the group that have finished.
+    pub fn completed_count(&self) -> Result<usize> {
+        Ok(self
+            .app
+            .get_task_results(&self.signature_ids)?
+            .iter()
+            .filter(|result| result.is_some())
+            .count())
+    }
+
+    /// Whether any of the finished task invocations in the group did not
+    /// succeed.
+    pub fn failed(&self) -> Result<bool> {
+        Ok(self
+            .app
+            .get_task_results(&self.signature_ids)?
+            .iter()
+            .flatten()
+            .any(|result| !matches!(result.outcome, TaskOutcome::Success(_))))
+    }
+
+    /// Get the results of the task invocations, in the order they were
+    /// queued, if all of them have finished.
+    ///
+    /// Returns `Error::Revoked` if any of them was revoked or expired.
+    pub fn try_get(&self) -> Result<Option<Vec<TaskResult<T>>>> {
+        self.app
+            .get_task_results(&self.signature_ids)?
+            .into_iter()
+            .map(|result| result.map(deserialize_result::<T>).transpose())
+            .collect::<Result<Option<Vec<_>>>>()
+    }
+
+    /// Wait for all task invocations to finish and get their results, in the
+    /// order they were queued.
+    ///
+    /// Returns `Error::Timeout` if they have not all finished within
+    /// `timeout`, and `Error::Revoked` if any of them was revoked or expired.
+    pub fn wait(&self, timeout: Duration) -> Result<Vec<TaskResult<T>>> {
+        let deadline = Instant::now() + timeout;
+        self.signature_ids
+            .iter()
+            .map(|signature_id| {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::Timeout(signature_id.clone()));
+                }
+                match self.app.wait_for_result(signature_id, deadline - now)? {
+                    Some(result_message) => deserialize_result::<T>(result_message),
+                    None => Err(Error::Timeout(signature_id.clone())),
+                }
+            })
+            .collect()
+    }
+
+    /// Remove the results and states of the task invocations from the result
+    /// backend.
+    pub fn forget(self) -> Result<()> {
+        self.signature_ids
+            .iter()
+            .try_for_each(|signature_id| self.app.forget_task_result(signature_id))
+    }
+}
+
+fn deserialize_result<T: Task>(result_message: ResultMessage) -> Result<TaskResult<T>> {
+    Ok(match result_message.outcome {
+        TaskOutcome::Success(value) => Ok(serde_json::from_str(&value)?),
+        TaskOutcome::Failure(error) => Err(TaskError::Failed(serde_json::from_str(&error)?)),
+        TaskOutcome::Panic(message) => Err(TaskError::Panicked(message)),
+        TaskOutcome::TimedOut => Err(TaskError::TimedOut),
+        TaskOutcome::UpstreamFailed(signature_id) => Err(TaskError::UpstreamFailed(signature_id)),
+        TaskOutcome::Rejected(reason) => Err(TaskError::Rejected(reason)),
+        TaskOutcome::Expired | TaskOutcome::Revoked => {
+            return Err(Error::Revoked(result_message.signature_id))
+        }
+    })
+}
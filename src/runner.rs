@@ -1,23 +1,36 @@
-use anyhow::Error;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use super::broker::Broker;
-use super::messages::ResultMessage;
-use super::task::{Signature, Task};
+use serde::Serialize;
+
+use super::broker::{Broker, TaskInfo, TaskState};
+use super::context::TaskContext;
+use super::error::Error;
+use super::messages::{ChainLink, Chord, Message, ResultMessage, TaskOutcome};
+use super::result::{FailedTask, TaskError};
+use super::task::{RetryPolicy, Signature, Task};
 use super::App;
 
+/// How many times a message that a worker failed to handle is put back on
+/// the queue, before it is rejected.
+const MAX_REDELIVERIES: u32 = 3;
+
 pub trait TaskRunnerTrait<B: Broker> {
-    fn run_task(&self, app: &App<B>) -> Result<(), Error>;
+    fn run_task(&self, app: &App<B>, worker_id: &str, context: &TaskContext) -> Result<(), Error>;
 }
 
 pub type TaskRunnerBuilderResult<B> = Result<Box<dyn TaskRunnerTrait<B>>, Error>;
-pub type TaskRunnerBuilder<B> = Box<dyn Fn(&str) -> TaskRunnerBuilderResult<B>>;
+pub type TaskRunnerBuilder<B> = Arc<dyn Fn(&Message) -> TaskRunnerBuilderResult<B> + Send + Sync>;
 
 pub fn build_task_runner<T: Task + 'static, B: Broker + 'static>(
-    serialized_signature: &str,
+    message: &Message,
 ) -> TaskRunnerBuilderResult<B> {
-    let signature = Signature::<T>::from_serialized(serialized_signature)?;
+    let signature = Signature::<T>::from_serialized(&message.signature)?;
     let task = T::from_signature(signature);
-    Ok(Box::new(TaskRunner::<T>::new(task)))
+    Ok(Box::new(TaskRunner::<T>::new(task, message.clone())))
 }
 
 struct TaskRunner<T>
@@ -25,27 +38,305 @@ where
     T: Task,
 {
     task: T,
+    message: Message,
 }
 
 impl<T> TaskRunner<T>
 where
     T: Task,
 {
-    fn new(task: T) -> Self {
-        Self { task }
+    fn new(task: T, message: Message) -> Self {
+        Self { task, message }
+    }
+
+    /// The message to queue for retrying the task after it failed with
+    /// `error`, if the task should be retried.
+    fn retry_message(&self, error: &T::ErrorType) -> Option<Message> {
+        if !T::is_retryable(error) {
+            return None;
+        }
+        retry_message(&self.message, &T::RETRY_POLICY)
     }
 }
 
 impl<T, B: Broker + 'static> TaskRunnerTrait<B> for TaskRunner<T>
 where
-    T: Task,
+    T: Task + 'static,
 {
-    fn run_task(&self, app: &App<B>) -> Result<(), Error> {
-        let result = T::run(&self.task.signature().arg);
-        app.store_task_result(ResultMessage {
-            result: serde_json::to_string(&result)?,
-            signature_id: self.task.signature().id.clone(),
-        })?;
-        Ok(())
+    fn run_task(&self, app: &App<B>, worker_id: &str, context: &TaskContext) -> Result<(), Error> {
+        if let Some(rate_limit) = &T::RATE_LIMIT {
+            if let Some(wait) = app.broker.take_rate_limit_token(T::ID, rate_limit)? {
+                // Over the limit, run the task once the next run is allowed.
+                app.set_task_state(&self.message, TaskState::Pending, None)?;
+                return app.requeue_message(&rate_limited_message(&self.message, wait));
+            }
+        }
+        app.set_task_state(&self.message, TaskState::Started, Some(worker_id))?;
+
+        let run_result = if T::SOFT_TIME_LIMIT.is_none() && T::HARD_TIME_LIMIT.is_none() {
+            Some(context.enter(|| catch_panic::<T>(&self.task.signature().arg)))
+        } else {
+            let signature = Signature::<T>::from_serialized(&self.message.signature)?;
+            run_with_time_limits(signature, context)
+        };
+        if context.is_cancelled() && app.is_revoked(&self.message.signature_id)? {
+            // The task was terminated, whatever it returned.
+            return app.store_task_outcome(&self.message, TaskOutcome::Revoked, worker_id);
+        }
+        let outcome = match run_result {
+            None => TaskOutcome::TimedOut,
+            Some(Ok(Ok(value))) => TaskOutcome::Success(serde_json::to_string(&value)?),
+            Some(Ok(Err(error))) => {
+                if let Some(retry_message) = self.retry_message(&error) {
+                    // The result is only stored once the retries are used up.
+                    app.set_task_state(&self.message, TaskState::Retrying, Some(worker_id))?;
+                    return app.requeue_message(&retry_message);
+                }
+                TaskOutcome::Failure(serde_json::to_string(&error)?)
+            }
+            Some(Err(payload)) => TaskOutcome::Panic(panic_message(payload)),
+        };
+        app.store_task_outcome(&self.message, outcome, worker_id)
+    }
+}
+
+type RunResult<T> = thread::Result<Result<<T as Task>::ReturnType, <T as Task>::ErrorType>>;
+
+/// Run the task, catching panics so that a buggy task can not take down the
+/// worker running it. The panic is recorded as the outcome of the task.
+fn catch_panic<T: Task>(arg: &T::ArgumentType) -> RunResult<T> {
+    panic::catch_unwind(AssertUnwindSafe(|| T::run(arg)))
+}
+
+/// Run the task on a thread of its own, cancelling its context when it hits
+/// the soft time limit and giving up on it when it hits the hard time limit.
+///
+/// Returns `None` if the task hit the hard time limit.
+fn run_with_time_limits<T: Task + 'static>(
+    signature: Signature<T>,
+    context: &TaskContext,
+) -> Option<RunResult<T>> {
+    let started = Instant::now();
+    let (result_sender, result_receiver) = mpsc::channel();
+    let task_context = context.clone();
+    thread::spawn(move || {
+        let result = task_context.enter(|| catch_panic::<T>(&signature.arg));
+        // The worker is no longer listening if it gave up on the task.
+        let _ = result_sender.send(result);
+    });
+
+    if let Some(soft_time_limit) = T::SOFT_TIME_LIMIT {
+        match result_receiver.recv_timeout(soft_time_limit) {
+            Ok(result) => return Some(result),
+            Err(_) => context.cancel(),
+        }
+    }
+    match T::HARD_TIME_LIMIT {
+        Some(hard_time_limit) => result_receiver
+            .recv_timeout(hard_time_limit.saturating_sub(started.elapsed()))
+            .ok(),
+        None => result_receiver.recv().ok(),
+    }
+}
+
+/// The message for the next attempt at a failed task, if the retry policy
+/// allows another attempt.
+pub(crate) fn retry_message(message: &Message, policy: &RetryPolicy) -> Option<Message> {
+    if message.attempt >= policy.max_attempts {
+        return None;
+    }
+
+    let delay = policy.delay_after_attempt(message.attempt);
+    Some(Message {
+        attempt: message.attempt + 1,
+        not_before: Some(SystemTime::now() + delay),
+        ..message.clone()
+    })
+}
+
+/// The message to put back on the queue after a worker failed to handle
+/// `message`, unless it has already been redelivered `MAX_REDELIVERIES`
+/// times and should be given up on.
+pub(crate) fn redelivered_message(message: &Message) -> Option<Message> {
+    if message.redeliveries >= MAX_REDELIVERIES {
+        return None;
+    }
+    Some(Message {
+        redeliveries: message.redeliveries + 1,
+        ..message.clone()
+    })
+}
+
+/// The message for running a task that was over its rate limit, once it has
+/// waited `wait`.
+pub(crate) fn rate_limited_message(message: &Message, wait: Duration) -> Message {
+    Message {
+        not_before: Some(SystemTime::now() + wait),
+        ..message.clone()
+    }
+}
+
+/// The state a task ends up in with the given outcome.
+pub(crate) fn final_state(outcome: &TaskOutcome) -> TaskState {
+    match outcome {
+        TaskOutcome::Success(_) => TaskState::Success,
+        TaskOutcome::Failure(_)
+        | TaskOutcome::Panic(_)
+        | TaskOutcome::TimedOut
+        | TaskOutcome::UpstreamFailed(_)
+        | TaskOutcome::Rejected(_) => TaskState::Failure,
+        TaskOutcome::Expired | TaskOutcome::Revoked => TaskState::Revoked,
+    }
+}
+
+/// The signature of a task queued by a worker once the task(s) before it
+/// finished, such as a chained task or callback, with an argument built from
+/// their outcome. Serializes the same way as `Signature`.
+#[derive(Serialize)]
+struct ChainedSignature<'a> {
+    arg: serde_json::Value,
+    id: &'a str,
+}
+
+/// The message for the next task in the chain of a task that succeeded with
+/// `value`, if there is one.
+pub(crate) fn next_in_chain(message: &Message, value: &str) -> Result<Option<Message>, Error> {
+    let Some((next, rest)) = message.chain.split_first() else {
+        return Ok(None);
+    };
+
+    let signature = ChainedSignature {
+        arg: serde_json::from_str(value)?,
+        id: &next.signature_id,
+    };
+    let mut next_message = link_message(next, serde_json::to_string(&signature)?);
+    next_message.chain = rest.to_vec();
+    Ok(Some(next_message))
+}
+
+/// The results and states to store for the rest of the chain of a task that
+/// did not succeed, which is never run.
+pub(crate) fn upstream_failures(message: &Message) -> Vec<(ResultMessage, TaskInfo)> {
+    message
+        .chain
+        .iter()
+        .map(|link| upstream_failure(link, &message.signature_id, message.queued_at))
+        .collect()
+}
+
+/// The result and state to store for a task that is never run, because the
+/// task with `failed_signature_id` did not succeed.
+fn upstream_failure(
+    link: &ChainLink,
+    failed_signature_id: &str,
+    queued_at: SystemTime,
+) -> (ResultMessage, TaskInfo) {
+    let result = ResultMessage {
+        signature_id: link.signature_id.clone(),
+        outcome: TaskOutcome::UpstreamFailed(failed_signature_id.to_string()),
+    };
+    let info = TaskInfo {
+        signature_id: link.signature_id.clone(),
+        state: TaskState::Failure,
+        worker_id: None,
+        queued_at,
+        updated_at: SystemTime::now(),
+    };
+    (result, info)
+}
+
+/// The messages for the success or error callbacks linked to a task that
+/// finished with `outcome`.
+pub(crate) fn callback_messages(
+    message: &Message,
+    outcome: &TaskOutcome,
+) -> Result<Vec<Message>, Error> {
+    let (links, arg) = match outcome {
+        TaskOutcome::Success(value) => (&message.link, serde_json::from_str(value)?),
+        TaskOutcome::Failure(_)
+        | TaskOutcome::Panic(_)
+        | TaskOutcome::TimedOut
+        | TaskOutcome::Rejected(_) => {
+            let error = match outcome {
+                TaskOutcome::Failure(error) => TaskError::Failed(serde_json::from_str(error)?),
+                TaskOutcome::Panic(panic_message) => TaskError::Panicked(panic_message.clone()),
+                TaskOutcome::Rejected(reason) => TaskError::Rejected(reason.clone()),
+                _ => TaskError::TimedOut,
+            };
+            let failed_task = FailedTask::<serde_json::Value> {
+                signature_id: message.signature_id.clone(),
+                error,
+            };
+            (&message.link_error, serde_json::to_value(failed_task)?)
+        }
+        // The task was never run, or never got to finish.
+        TaskOutcome::Expired | TaskOutcome::Revoked | TaskOutcome::UpstreamFailed(_) => {
+            return Ok(Vec::new())
+        }
+    };
+
+    links
+        .iter()
+        .map(|link| {
+            let signature = ChainedSignature {
+                arg: arg.clone(),
+                id: &link.signature_id,
+            };
+            Ok(link_message(link, serde_json::to_string(&signature)?))
+        })
+        .collect()
+}
+
+/// What to do once all tasks in the header of a chord have finished.
+pub(crate) enum ChordCompletion {
+    /// Queue the callback with the return values of the header.
+    Queue(Box<Message>),
+    /// Fail the callback, because a task in the header did not succeed.
+    Fail(ResultMessage, TaskInfo),
+}
+
+/// Decide what to do with the callback of a chord, given the results of the
+/// tasks in its header.
+pub(crate) fn complete_chord(
+    chord: &Chord,
+    results: Vec<Option<ResultMessage>>,
+    queued_at: SystemTime,
+) -> Result<ChordCompletion, Error> {
+    let mut values = Vec::with_capacity(results.len());
+    for (signature_id, result) in chord.header.iter().zip(results) {
+        match result.map(|result| result.outcome) {
+            Some(TaskOutcome::Success(value)) => values.push(serde_json::from_str(&value)?),
+            _ => {
+                let (result, info) = upstream_failure(&chord.callback, signature_id, queued_at);
+                return Ok(ChordCompletion::Fail(result, info));
+            }
+        }
+    }
+
+    let signature = ChainedSignature {
+        arg: serde_json::Value::Array(values),
+        id: &chord.callback.signature_id,
+    };
+    Ok(ChordCompletion::Queue(Box::new(link_message(
+        &chord.callback,
+        serde_json::to_string(&signature)?,
+    ))))
+}
+
+/// The message for running the task of a link with `signature`.
+fn link_message(link: &ChainLink, signature: String) -> Message {
+    let mut message = Message::new(link.task_id.clone(), link.signature_id.clone(), signature);
+    message.queue = link.queue.clone();
+    message.priority = link.priority;
+    message
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Task panicked with a non-string payload".to_string()
     }
 }
@@ -0,0 +1,275 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+pub use chrono_tz::Tz;
+use ulid::Ulid;
+
+use super::broker::Broker;
+use super::error::{Error, Result};
+use super::task::Task;
+use super::App;
+
+const DEFAULT_TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a scheduler keeps the lock without renewing it, before another
+/// scheduler may take over.
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many missed runs of a periodic task are queued in a single tick.
+const DEFAULT_MAX_CATCH_UP_RUNS: usize = 10;
+
+/// When a periodic task is due.
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    /// Every `Duration`, counting from when the task was last due.
+    Interval(Duration),
+    /// Whenever the cron expression matches, in the given timezone.
+    Cron(Box<cron::Schedule>, Tz),
+}
+
+impl Schedule {
+    /// Due every `interval`, which must not be zero.
+    pub fn interval(interval: Duration) -> Result<Self> {
+        let schedule = Self::Interval(interval);
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    /// Parse a cron expression, with fields for seconds, minutes, hours, day
+    /// of month, month, day of week and optionally year, such as
+    /// `"0 30 9 * * Mon-Fri"`. The expression is matched in `timezone`.
+    pub fn cron(expression: &str, timezone: Tz) -> Result<Self> {
+        let schedule = cron::Schedule::from_str(expression)
+            .map_err(|error| Error::InvalidSchedule(format!("'{expression}': {error}")))?;
+        Ok(Self::Cron(Box::new(schedule), timezone))
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            Self::Interval(interval) if interval.is_zero() => Err(Error::InvalidSchedule(
+                "the interval must not be zero".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The first time the task is due after `time`.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        match self {
+            Self::Interval(interval) => time.checked_add(*interval),
+            Self::Cron(schedule, timezone) => schedule
+                .after(&DateTime::<Utc>::from(time).with_timezone(timezone))
+                .next()
+                .map(SystemTime::from),
+        }
+    }
+}
+
+type QueueFn<B> = Box<dyn Fn(&App<B>) -> Result<()> + Send + Sync>;
+
+/// A periodic task, queued by the scheduler whenever its schedule is due.
+struct ScheduleEntry<B: Broker + 'static> {
+    /// Identifies the entry in the broker, where its last run is kept.
+    name: String,
+    task_id: &'static str,
+    schedule: Schedule,
+    queue: QueueFn<B>,
+}
+
+/// Queues periodic tasks when their schedules are due.
+///
+/// When each task was last due is kept in the broker, so a restarted
+/// scheduler picks up where the last one left off, queueing any runs it
+/// missed while it was down. Only one scheduler is active at a time: the
+/// one holding a lock in the broker. Others stand by, and take over if the
+/// active one stops renewing the lock.
+pub struct Scheduler<B: Broker + 'static> {
+    app: App<B>,
+    pub id: String,
+    entries: Vec<ScheduleEntry<B>>,
+    tick_interval: Duration,
+    lock_timeout: Duration,
+    max_catch_up_runs: usize,
+    stopping: AtomicBool,
+}
+
+pub struct SchedulerBuilder<B: Broker + 'static> {
+    app: App<B>,
+    entries: Vec<ScheduleEntry<B>>,
+    tick_interval: Duration,
+    lock_timeout: Duration,
+    max_catch_up_runs: usize,
+}
+
+impl<B: Broker + 'static> SchedulerBuilder<B> {
+    /// Queue the task `T` with `arg` whenever `schedule` is due.
+    ///
+    /// `name` identifies the periodic task across restarts, and must be
+    /// unique. The first run is counted from when a scheduler first sees the
+    /// name, so runs from before then are not made up for.
+    pub fn add<T>(mut self, name: &str, schedule: Schedule, arg: T::ArgumentType) -> Self
+    where
+        T: Task + 'static,
+        T::ArgumentType: Clone + Sync,
+    {
+        self.entries.push(ScheduleEntry {
+            name: name.to_string(),
+            task_id: T::ID,
+            schedule,
+            queue: Box::new(move |app| app.queue_task::<T>(arg.clone()).map(|_| ())),
+        });
+        self
+    }
+
+    /// How often the scheduler checks for due tasks. Defaults to every
+    /// second.
+    pub fn tick_interval(mut self, tick_interval: Duration) -> Self {
+        self.tick_interval = tick_interval;
+        self
+    }
+
+    /// How long the scheduler holds the lock without renewing it, before
+    /// another scheduler may take over. Must be longer than the tick
+    /// interval. Defaults to 30 seconds.
+    pub fn lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+
+    /// How many runs of each periodic task the scheduler queues in a single
+    /// tick, when it has fallen behind. The rest are queued on later ticks.
+    /// Defaults to 10.
+    pub fn max_catch_up_runs(mut self, max_catch_up_runs: usize) -> Self {
+        self.max_catch_up_runs = max_catch_up_runs;
+        self
+    }
+
+    pub fn build(self) -> Result<Scheduler<B>> {
+        for entry in &self.entries {
+            entry.schedule.validate()?;
+        }
+        if let Some(entry) = self
+            .entries
+            .iter()
+            .find(|entry| !self.app.is_registered(entry.task_id))
+        {
+            return Err(Error::NotRegistered(entry.task_id.into()));
+        }
+
+        Ok(Scheduler {
+            app: self.app,
+            id: Ulid::new().to_string(),
+            entries: self.entries,
+            tick_interval: self.tick_interval,
+            lock_timeout: self.lock_timeout,
+            max_catch_up_runs: self.max_catch_up_runs,
+            stopping: AtomicBool::new(false),
+        })
+    }
+}
+
+impl<B: Broker + 'static> Scheduler<B> {
+    /// Configure a new scheduler, queueing tasks through `app`.
+    pub fn builder(app: &App<B>) -> SchedulerBuilder<B> {
+        SchedulerBuilder {
+            app: app.clone(),
+            entries: Vec::new(),
+            tick_interval: DEFAULT_TICK_INTERVAL,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            max_catch_up_runs: DEFAULT_MAX_CATCH_UP_RUNS,
+        }
+    }
+
+    /// Queue the tasks that have become due since they were last due, if
+    /// this scheduler holds the lock.
+    ///
+    /// Returns the number of tasks queued.
+    pub fn tick(&self) -> Result<usize> {
+        if !self.renew_lock()? {
+            return Ok(0);
+        }
+
+        let now = SystemTime::now();
+        let mut queued = 0;
+        for entry in &self.entries {
+            let Some(mut last_run) = self.app.broker.get_schedule_last_run(&entry.name)? else {
+                self.app
+                    .broker
+                    .advance_schedule_last_run(&entry.name, None, now, &self.id)?;
+                continue;
+            };
+            let mut entry_queued = 0;
+            while let Some(due) = entry
+                .schedule
+                .next_after(last_run)
+                .filter(|due| *due <= now)
+            {
+                if entry_queued == self.max_catch_up_runs {
+                    break;
+                }
+                // Stop if a standby scheduler took over while catching up.
+                if queued > 0 && !self.renew_lock()? {
+                    return Ok(queued);
+                }
+                // Claim the run before queueing it, so that it is not queued
+                // again by a scheduler that took over in the meantime. A run
+                // that fails to be queued is skipped.
+                let claimed = self.app.broker.advance_schedule_last_run(
+                    &entry.name,
+                    Some(last_run),
+                    due,
+                    &self.id,
+                )?;
+                if !claimed {
+                    return Ok(queued);
+                }
+                (entry.queue)(&self.app)?;
+                last_run = due;
+                entry_queued += 1;
+                queued += 1;
+            }
+        }
+        Ok(queued)
+    }
+
+    /// Take or extend the lock, returning whether this scheduler holds it.
+    fn renew_lock(&self) -> Result<bool> {
+        self.app
+            .broker
+            .acquire_scheduler_lock(&self.id, self.lock_timeout)
+    }
+
+    /// Queue tasks as they become due, until `stop` is called.
+    pub fn run(&self) -> Result<()> {
+        self.stopping.store(false, Ordering::SeqCst);
+        while !self.stopping.load(Ordering::SeqCst) {
+            self.tick()?;
+            thread::sleep(self.tick_interval);
+        }
+        self.app.broker.release_scheduler_lock(&self.id)
+    }
+
+    /// Make `run` return after its current tick.
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<B: Broker + 'static> Drop for Scheduler<B> {
+    fn drop(&mut self) {
+        // Let a standby scheduler take over right away, instead of waiting
+        // for the lock to time out.
+        self.app
+            .broker
+            .release_scheduler_lock(&self.id)
+            .unwrap_or_else(|_| {
+                println!(
+                    "Unable to release the scheduler lock held by scheduler ID {}.",
+                    &self.id
+                );
+            });
+    }
+}
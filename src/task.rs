@@ -1,5 +1,10 @@
-use anyhow::Error;
+use super::error::Error;
+use super::messages::{DEFAULT_PRIORITY, DEFAULT_QUEUE};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::time::Duration;
 
 pub trait Task: Sized
 where
@@ -7,15 +12,65 @@ where
     Self::ArgumentType: for<'a> Deserialize<'a>,
     Self::ReturnType: Serialize,
     Self::ReturnType: for<'a> Deserialize<'a>,
+    Self::ErrorType: Serialize,
+    Self::ErrorType: for<'a> Deserialize<'a>,
 {
-    type ArgumentType;
-    type ReturnType;
+    type ArgumentType: Send + 'static;
+    type ReturnType: Send + 'static;
+    type ErrorType: Send + 'static;
 
     const ID: &'static str;
 
+    /// The queue the task is sent to, unless it is queued with another one.
+    const QUEUE: &'static str = DEFAULT_QUEUE;
+
+    /// The priority of the task within its queue, from 0 up to
+    /// `MAX_PRIORITY`, unless it is queued with another one. Higher priority
+    /// tasks are run first.
+    const PRIORITY: u8 = DEFAULT_PRIORITY;
+
+    /// How failed invocations of the task are retried. Defaults to no retries.
+    const RETRY_POLICY: RetryPolicy = RetryPolicy::no_retries();
+
+    /// How often the task may be run, across all workers sharing the broker.
+    /// Invocations picked up over the limit are delayed until a run is
+    /// allowed. Defaults to no limit.
+    const RATE_LIMIT: Option<RateLimit> = None;
+
+    /// How long the task may run before it is asked to stop, by cancelling
+    /// its `TaskContext`. Defaults to no limit.
+    const SOFT_TIME_LIMIT: Option<Duration> = None;
+
+    /// How long the task may run before the worker gives up on it, failing
+    /// it as timed out. The thread running the task is abandoned, and keeps
+    /// running until the task returns. Defaults to no limit.
+    const HARD_TIME_LIMIT: Option<Duration> = None;
+
     fn from_signature(signature: Signature<Self>) -> Self;
 
-    fn run(arg: &Self::ArgumentType) -> Self::ReturnType;
+    /// Run the task.
+    ///
+    /// Returning an error marks the task invocation as failed, and the error
+    /// is stored as the result of the invocation.
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType>;
+
+    /// Whether a failed invocation returning `error` should be retried.
+    ///
+    /// Only consulted while the task has attempts left according to
+    /// `RETRY_POLICY`. Panics are never retried.
+    fn is_retryable(_error: &Self::ErrorType) -> bool {
+        true
+    }
+
+    /// The key that identifies duplicate invocations of the task with `arg`,
+    /// if they should be deduplicated. While an invocation with the key is
+    /// queued or running, queueing the task again with the same key returns
+    /// that invocation instead of queueing another one. Defaults to no key.
+    ///
+    /// Tasks queued as part of a chain or chord are never deduplicated.
+    fn dedup_key(_arg: &Self::ArgumentType) -> Option<String> {
+        None
+    }
 
     /// Get the signature used to created the task instance
     fn signature(&self) -> &Signature<Self>;
@@ -38,3 +93,170 @@ where
         Ok(serde_json::from_str(signature)?)
     }
 }
+
+/// A task whose `run` is asynchronous, run by an `AsyncWorker`.
+///
+/// Queued async tasks use the same message format as `Task`, so they can be
+/// queued next to sync tasks.
+#[cfg(feature = "async")]
+pub trait AsyncTask: Send + Sync + 'static
+where
+    Self::ArgumentType: Serialize,
+    Self::ArgumentType: for<'a> Deserialize<'a>,
+    Self::ReturnType: Serialize,
+    Self::ReturnType: for<'a> Deserialize<'a>,
+    Self::ErrorType: Serialize,
+    Self::ErrorType: for<'a> Deserialize<'a>,
+{
+    type ArgumentType: Send + Sync + 'static;
+    type ReturnType: Send + 'static;
+    type ErrorType: Send + 'static;
+
+    const ID: &'static str;
+
+    /// The queue the task is sent to, unless it is queued with another one.
+    const QUEUE: &'static str = DEFAULT_QUEUE;
+
+    /// The priority of the task within its queue, from 0 up to
+    /// `MAX_PRIORITY`, unless it is queued with another one. Higher priority
+    /// tasks are run first.
+    const PRIORITY: u8 = DEFAULT_PRIORITY;
+
+    /// How failed invocations of the task are retried. Defaults to no retries.
+    const RETRY_POLICY: RetryPolicy = RetryPolicy::no_retries();
+
+    /// How often the task may be run, across all workers sharing the broker.
+    /// Invocations picked up over the limit are delayed until a run is
+    /// allowed. Defaults to no limit.
+    const RATE_LIMIT: Option<RateLimit> = None;
+
+    /// How long the task may run before it is asked to stop, by cancelling
+    /// its `TaskContext`. Defaults to no limit.
+    const SOFT_TIME_LIMIT: Option<Duration> = None;
+
+    /// How long the task may run before it is aborted and failed as timed
+    /// out. Defaults to no limit.
+    const HARD_TIME_LIMIT: Option<Duration> = None;
+
+    /// Run the task.
+    ///
+    /// Returning an error marks the task invocation as failed, and the error
+    /// is stored as the result of the invocation.
+    fn run(
+        arg: &Self::ArgumentType,
+    ) -> impl Future<Output = Result<Self::ReturnType, Self::ErrorType>> + Send;
+
+    /// Whether a failed invocation returning `error` should be retried.
+    fn is_retryable(_error: &Self::ErrorType) -> bool {
+        true
+    }
+
+    /// The key that identifies duplicate invocations of the task with `arg`,
+    /// if they should be deduplicated. While an invocation with the key is
+    /// queued or running, queueing the task again with the same key returns
+    /// that invocation instead of queueing another one. Defaults to no key.
+    ///
+    /// Tasks queued as part of a chain or chord are never deduplicated.
+    fn dedup_key(_arg: &Self::ArgumentType) -> Option<String> {
+        None
+    }
+}
+
+/// The signature of an async task invocation. Serializes the same way as
+/// `Signature`.
+#[cfg(feature = "async")]
+#[derive(Serialize, Deserialize)]
+pub(crate) struct AsyncSignature<A> {
+    pub arg: A,
+    pub id: String,
+}
+
+/// The delay between attempts of a failed task.
+#[derive(Clone, Copy, Debug)]
+pub enum Backoff {
+    /// Wait the same amount of time before each retry.
+    Fixed(Duration),
+    /// Double the wait for each retry, starting at `base` and never waiting
+    /// longer than `max`.
+    Exponential { base: Duration, max: Duration },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// The total number of times the task is attempted, including the first.
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+    /// Pick a random delay between zero and the backoff delay, to avoid
+    /// failed tasks being retried in lockstep.
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    pub const fn no_retries() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Backoff::Fixed(Duration::ZERO),
+            jitter: false,
+        }
+    }
+
+    /// The delay before the retry following the given (1-indexed) attempt.
+    pub fn delay_after_attempt(&self, attempt: u32) -> Duration {
+        let delay = match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { base, max } => 2u32
+                .checked_pow(attempt.saturating_sub(1))
+                .and_then(|factor| base.checked_mul(factor))
+                .map_or(max, |delay| delay.min(max)),
+        };
+
+        if self.jitter {
+            delay.mul_f64(rand::random::<f64>())
+        } else {
+            delay
+        }
+    }
+}
+
+/// How often a task may be run, enforced by a token bucket in the broker.
+///
+/// The bucket holds up to `max_runs` tokens, one taken for each run, and is
+/// refilled evenly over `per`. Bursts of up to `max_runs` runs are allowed
+/// when the bucket is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max_runs: NonZeroU32,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    /// At most `max_runs` runs `per` period, where `max_runs` must be at
+    /// least 1.
+    pub fn new(max_runs: u32, per: Duration) -> Result<Self, Error> {
+        let max_runs = NonZeroU32::new(max_runs).ok_or_else(|| {
+            Error::InvalidRateLimit("the number of runs must be at least 1".to_string())
+        })?;
+        Ok(Self { max_runs, per })
+    }
+
+    pub const fn per_second(max_runs: NonZeroU32) -> Self {
+        Self {
+            max_runs,
+            per: Duration::from_secs(1),
+        }
+    }
+
+    pub const fn per_minute(max_runs: NonZeroU32) -> Self {
+        Self {
+            max_runs,
+            per: Duration::from_secs(60),
+        }
+    }
+
+    pub const fn per_hour(max_runs: NonZeroU32) -> Self {
+        Self {
+            max_runs,
+            per: Duration::from_secs(60 * 60),
+        }
+    }
+}
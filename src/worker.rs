@@ -1,80 +1,388 @@
-use anyhow::Result;
-use std::{ops::Drop, thread, time};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::{ops::Drop, panic, thread, time};
 use ulid::Ulid;
 
-use super::broker::{Broker, WorkerInfo, WorkerState};
-use super::messages::Command;
+use super::broker::{Broker, TaskState, WorkerInfo, WorkerState};
+use super::context::TaskContext;
+use super::error::{Error, Result};
+use super::messages::{Command, Message, TaskOutcome, DEFAULT_QUEUE};
 use super::App;
 
-const SLEEP_TIME: time::Duration = time::Duration::from_millis(500);
+const DEFAULT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(500);
 
-pub struct Worker<'a, B: Broker + 'static> {
-    app: &'a App<'a, B>,
+const DEFAULT_VISIBILITY_TIMEOUT: time::Duration = time::Duration::from_secs(60 * 60);
+
+/// How long a running worker stays registered after its last heartbeat. It
+/// sends one every quarter of that, from a thread of its own so that long
+/// running tasks or poll intervals don't hold it up.
+const WORKER_TTL: time::Duration = time::Duration::from_secs(60);
+
+pub struct Worker<B: Broker + 'static> {
+    app: Arc<App<B>>,
     pub id: String,
+    poll_interval: time::Duration,
+    concurrency: usize,
+    prefetch: usize,
+    visibility_timeout: time::Duration,
+    /// The queues the worker consumes, in order of priority.
+    queues: Vec<String>,
+    /// Set when the worker is stopping, so the threads in the pool do not
+    /// start on any more messages.
+    stopping: AtomicBool,
+    /// The contexts of the running tasks, by signature ID.
+    running: Mutex<HashMap<String, TaskContext>>,
 }
 
-impl<'a, B: Broker + 'static> Worker<'a, B> {
-    /// Create a new worker instance.
-    ///
-    /// Note that since this takes a reference to the `App` instance and registering a task
-    /// requires mutating the `App` the borrow checker prevents any more tasks from being
-    /// registered. All tasks must be registered by the time the worker is initialized.
-    pub fn new(app: &'a App<'a, B>) -> Result<Self> {
+pub struct WorkerBuilder<B: Broker + 'static> {
+    app: Arc<App<B>>,
+    poll_interval: time::Duration,
+    concurrency: usize,
+    prefetch: usize,
+    visibility_timeout: time::Duration,
+    queues: Vec<String>,
+}
+
+impl<B: Broker + 'static> WorkerBuilder<B> {
+    /// How long the worker waits for a message before checking for commands
+    /// again. Brokers that can not block while waiting for a message instead
+    /// sleep this long whenever the queue is empty.
+    pub fn poll_interval(mut self, poll_interval: time::Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How many tasks the worker runs in parallel, each on its own thread.
+    /// Defaults to one.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// How many messages the worker reserves ahead of time, waiting for a
+    /// thread to become available. Defaults to none.
+    pub fn prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    /// The queues the worker takes messages off, in order of priority: a
+    /// message is only taken off a queue when the queues before it are empty.
+    /// Defaults to the default queue.
+    pub fn queues(mut self, queues: &[&str]) -> Self {
+        self.queues = queues.iter().map(|queue| queue.to_string()).collect();
+        self
+    }
+
+    /// How long any worker may hold a reserved message before it is assumed
+    /// to have died, and the message is put back on the queue by this worker.
+    /// Must be longer than any task takes to run. Defaults to an hour.
+    pub fn visibility_timeout(mut self, visibility_timeout: time::Duration) -> Self {
+        self.visibility_timeout = visibility_timeout;
+        self
+    }
+
+    pub fn build(self) -> Result<Worker<B>> {
         let id = Ulid::new().to_string();
-        app.update_worker_info(WorkerInfo {
+        self.app.update_worker_info(WorkerInfo {
             state: WorkerState::Pending,
             id: id.clone(),
         })?;
 
-        Ok(Self { app, id })
+        Ok(Worker {
+            app: self.app,
+            id,
+            poll_interval: self.poll_interval,
+            concurrency: self.concurrency,
+            prefetch: self.prefetch,
+            visibility_timeout: self.visibility_timeout,
+            queues: self.queues,
+            stopping: AtomicBool::new(false),
+            running: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl<B: Broker + 'static> Worker<B> {
+    /// Create a new worker instance with the default configuration.
+    ///
+    /// Note that since the `App` is shared with the worker and registering a
+    /// task requires mutating the `App`, all tasks must be registered by the
+    /// time the worker is initialized.
+    pub fn new(app: Arc<App<B>>) -> Result<Self> {
+        Self::builder(app).build()
+    }
+
+    /// Configure a new worker instance.
+    pub fn builder(app: Arc<App<B>>) -> WorkerBuilder<B> {
+        WorkerBuilder {
+            app,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            concurrency: 1,
+            prefetch: 0,
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            queues: vec![DEFAULT_QUEUE.to_string()],
+        }
     }
 
     /// Listen for and process queued task messages.
     ///
     /// Continually check the queue for new messages and run the corresponding
-    /// task. Checks the command queue before each new message and obeys the
-    /// commands there. Keeps processing until receiving a stop command.
+    /// tasks on the worker's thread pool. Checks the command queue before each
+    /// new message, and at least every poll interval while all threads are
+    /// busy, and obeys the commands there. Keeps processing until receiving a
+    /// stop command, then waits for the running tasks to finish.
     pub fn listen_for_messages(&self) -> Result<()> {
+        self.send_heartbeat()?;
+        self.stopping.store(false, Ordering::SeqCst);
+
+        let (job_sender, job_receiver) = mpsc::channel();
+        let job_receiver = Mutex::new(job_receiver);
+        let (done_sender, done_receiver) = mpsc::channel();
+
+        let (heartbeat_sender, heartbeat_receiver) = mpsc::channel::<()>();
+
+        let result = thread::scope(|scope| {
+            let pool: Vec<_> = (0..self.concurrency)
+                .map(|_| {
+                    let job_receiver = &job_receiver;
+                    let done_sender = done_sender.clone();
+                    scope.spawn(move || self.run_jobs(job_receiver, done_sender))
+                })
+                .collect();
+            scope.spawn(move || self.send_heartbeats(&heartbeat_receiver));
+
+            let result = self.dispatch_messages(job_sender, &done_receiver);
+            self.stopping.store(true, Ordering::SeqCst);
+
+            // Keep sending heartbeats until the running tasks have finished.
+            let finished: Vec<_> = pool.into_iter().map(|thread| thread.join()).collect();
+            drop(heartbeat_sender);
+            for finished in finished {
+                if let Err(panic) = finished {
+                    panic::resume_unwind(panic);
+                }
+            }
+            result
+        });
+
+        // Report failures of messages that finished after dispatching
+        // stopped.
+        drop(done_sender);
+        result?;
+        for result in done_receiver.try_iter() {
+            self.log_message_error(result);
+        }
+
         self.app.update_worker_info(WorkerInfo {
             id: self.id.clone(),
-            state: WorkerState::Running,
+            state: WorkerState::Stopped,
         })?;
+        Ok(())
+    }
+
+    pub fn take_first_task_in_queue(&self) -> Result<()> {
+        self.app.broker.promote_due_messages(&self.queues)?;
+        let message = self.app.broker.reserve_message(&self.id, &self.queues)?;
+        match message {
+            Some(m) if !m.is_due() => {
+                self.postpone_message(&m)?;
+                Err(Error::NoDueMessage)
+            }
+            Some(m) => self.handle_message(&m),
+            None => Err(Error::NoDueMessage),
+        }
+    }
 
+    /// Reserve messages and send them to the thread pool until told to stop,
+    /// never holding more than the pool and prefetch limit allow.
+    fn dispatch_messages(
+        &self,
+        job_sender: mpsc::Sender<Message>,
+        done_receiver: &mpsc::Receiver<Result<()>>,
+    ) -> Result<()> {
+        let mut in_flight = 0;
         loop {
+            for result in done_receiver.try_iter() {
+                in_flight -= 1;
+                self.log_message_error(result);
+            }
+
             match self.app.broker.pop_command(&self.id)? {
                 None => (),
                 Some(Command::StopWorker) => {
-                    break;
+                    return Ok(());
                 }
+                Some(Command::TerminateTask(signature_id)) => self.terminate_task(&signature_id),
             };
 
-            match self.app.broker.pop_message()? {
+            if in_flight >= self.concurrency + self.prefetch {
+                // Wait for a thread to become available, but keep checking
+                // for commands while doing so.
+                if let Ok(result) = done_receiver.recv_timeout(self.poll_interval) {
+                    in_flight -= 1;
+                    self.log_message_error(result);
+                }
+                continue;
+            }
+
+            self.app.broker.promote_due_messages(&self.queues)?;
+            match self.app.broker.reserve_message_blocking(
+                &self.id,
+                &self.queues,
+                self.poll_interval,
+            )? {
                 None => {
-                    // If there are no messages on the queue, wait a little
-                    // instead of immediately re-checking for messages.
-                    thread::sleep(SLEEP_TIME);
+                    // Use the idle time to recover messages held by workers
+                    // that died.
+                    self.app.requeue_unacked_messages(self.visibility_timeout)?;
+                }
+                Some(m) if !m.is_due() => {
+                    // The task is scheduled to run later, put it back and
+                    // wait a little so we do not spin on undue messages.
+                    self.postpone_message(&m)?;
+                    thread::sleep(self.poll_interval);
+                }
+                Some(m) => {
+                    job_sender.send(m).map_err(|_| {
+                        Error::TaskFailed("All threads in the worker pool have died".into())
+                    })?;
+                    in_flight += 1;
                 }
-                Some(m) => self.app.handle_message(&m)?,
             }
         }
+    }
 
-        self.app.update_worker_info(WorkerInfo {
+    /// Handle messages sent to the thread pool until the sender is dropped.
+    fn run_jobs(
+        &self,
+        job_receiver: &Mutex<mpsc::Receiver<Message>>,
+        done_sender: mpsc::Sender<Result<()>>,
+    ) {
+        loop {
+            let received = job_receiver.lock().expect("Failed to aquire lock").recv();
+            let message = match received {
+                Ok(message) => message,
+                Err(_) => return,
+            };
+
+            let result = if self.stopping.load(Ordering::SeqCst) {
+                // Leave prefetched messages for other workers.
+                self.app.broker.nack_message(&self.id, &message)
+            } else {
+                self.handle_message(&message)
+            };
+            if done_sender.send(result).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Handle a reserved message, acknowledging it once done. If handling
+    /// fails the message is put back on the queue, unless it has failed too
+    /// many times already.
+    ///
+    /// Messages for revoked tasks are acknowledged without running the task.
+    fn handle_message(&self, message: &Message) -> Result<()> {
+        let result = self
+            .app
+            .is_revoked(&message.signature_id)
+            .and_then(|revoked| {
+                if revoked {
+                    self.app
+                        .store_task_outcome(message, TaskOutcome::Revoked, &self.id)
+                } else {
+                    self.run_message(message)
+                }
+            });
+
+        match result {
+            Ok(()) => self.app.broker.ack_message(&self.id, message),
+            Err(e) => {
+                self.app.redeliver_message(message, &self.id, &e)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Run the task of a message, keeping its context around so the task can
+    /// be terminated while it runs.
+    fn run_message(&self, message: &Message) -> Result<()> {
+        let context = TaskContext::new(message.signature_id.clone());
+        self.running
+            .lock()
+            .expect("Failed to aquire lock")
+            .insert(message.signature_id.clone(), context.clone());
+
+        let result = self
+            .app
+            .set_task_state(message, TaskState::Received, Some(&self.id))
+            .and_then(|_| self.app.handle_message(message, &self.id, &context));
+
+        self.running
+            .lock()
+            .expect("Failed to aquire lock")
+            .remove(&message.signature_id);
+        result
+    }
+
+    /// Ask a running task to stop, by cancelling its context. Does nothing if
+    /// the task is not running on this worker.
+    fn terminate_task(&self, signature_id: &str) {
+        if let Some(context) = self
+            .running
+            .lock()
+            .expect("Failed to aquire lock")
+            .get(signature_id)
+        {
+            context.cancel();
+        }
+    }
+
+    /// Keep the worker registered as running, so that it is not assumed to
+    /// have died.
+    fn send_heartbeat(&self) -> Result<()> {
+        let info = WorkerInfo {
             id: self.id.clone(),
-            state: WorkerState::Stopped,
-        })?;
-        Ok(())
+            state: WorkerState::Running,
+        };
+        self.app.broker.worker_heartbeat(info, WORKER_TTL)
     }
 
-    pub fn take_first_task_in_queue(&self) -> Result<()> {
-        let message = self.app.broker.pop_message()?;
-        match message {
-            Some(m) => self.app.handle_message(&m),
-            None => Err(anyhow::anyhow!("No messages in queue")),
+    /// Send a heartbeat every quarter of the worker TTL until `stop` is
+    /// disconnected. Failing to send one is reported, and tried again next
+    /// time.
+    fn send_heartbeats(&self, stop: &mpsc::Receiver<()>) {
+        while let Err(mpsc::RecvTimeoutError::Timeout) = stop.recv_timeout(WORKER_TTL / 4) {
+            if let Err(error) = self.send_heartbeat() {
+                println!(
+                    "Worker ID {} failed to send a heartbeat: {}",
+                    &self.id, error
+                );
+            }
         }
     }
+
+    /// Report a failure to handle a message. The message itself has been put
+    /// back on the queue or rejected, so the worker carries on with the next.
+    fn log_message_error(&self, result: Result<()>) {
+        if let Err(error) = result {
+            println!(
+                "Worker ID {} failed to handle a message: {}",
+                &self.id, error
+            );
+        }
+    }
+
+    /// Put a reserved message that is not due yet at the back of the queue.
+    fn postpone_message(&self, message: &Message) -> Result<()> {
+        self.app.requeue_message(message)?;
+        self.app.broker.ack_message(&self.id, message)
+    }
 }
 
-impl<'a, B: Broker + 'static> Drop for Worker<'a, B> {
+impl<B: Broker + 'static> Drop for Worker<B> {
     fn drop(&mut self) {
         // Remove the worker from the worker register.
         // We don't want to panic here, so instead we just inform about the
@@ -0,0 +1,229 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    async_app::AsyncApp,
+    async_worker::AsyncWorker,
+    broker::TaskState,
+    messages::{Command, TaskOutcome},
+    task::{AsyncTask, Signature, Task},
+    worker::Worker,
+    App,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Waits for a while without blocking the runtime, keeping track of how many
+/// instances run at once.
+struct AsyncSleepTask;
+
+impl AsyncTask for AsyncSleepTask {
+    type ArgumentType = u64;
+    type ReturnType = u64;
+    type ErrorType = ();
+
+    const ID: &'static str = "AsyncSleepTask";
+
+    async fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+        MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(*arg)).await;
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+        Ok(*arg)
+    }
+}
+
+struct AsyncPanickingTask;
+
+impl AsyncTask for AsyncPanickingTask {
+    type ArgumentType = ();
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "AsyncPanickingTask";
+
+    async fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        panic!("Something went terribly wrong")
+    }
+}
+
+/// Never finishes in time.
+struct AsyncStuckTask;
+
+impl AsyncTask for AsyncStuckTask {
+    type ArgumentType = ();
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "AsyncStuckTask";
+
+    const HARD_TIME_LIMIT: Option<Duration> = Some(Duration::from_millis(50));
+
+    async fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        Ok(())
+    }
+}
+
+/// A sync task sharing its ID and argument type with `AsyncDoubleTask`.
+struct DoubleTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for DoubleTask {
+    type ArgumentType = u32;
+    type ReturnType = u32;
+    type ErrorType = ();
+
+    const ID: &'static str = "DoubleTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg * 2)
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+struct AsyncDoubleTask;
+
+impl AsyncTask for AsyncDoubleTask {
+    type ArgumentType = u32;
+    type ReturnType = u32;
+    type ErrorType = ();
+
+    const ID: &'static str = "DoubleTask";
+
+    async fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg * 2)
+    }
+}
+
+async fn wait_for_outcome(
+    app: &AsyncApp<InMemoryTestBroker>,
+    signature_id: &str,
+) -> anyhow::Result<TaskOutcome> {
+    loop {
+        if let Some(result) = app.get_task_result(signature_id).await? {
+            return Ok(result.outcome);
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_async_worker_runs_tasks_concurrently() -> anyhow::Result<()> {
+    let mut app = AsyncApp::new(InMemoryTestBroker::new());
+    app.register_task::<AsyncSleepTask>();
+    let app = Arc::new(app);
+
+    let worker = AsyncWorker::builder(app.clone())
+        .concurrency(3)
+        .poll_interval(Duration::from_millis(10))
+        .build()
+        .await?;
+
+    let mut signature_ids = Vec::new();
+    for _ in 0..6 {
+        signature_ids.push(app.queue_task::<AsyncSleepTask>(100).await?);
+    }
+
+    let (listened, outcomes) = tokio::join!(worker.listen_for_messages(), async {
+        let mut outcomes = Vec::new();
+        for signature_id in &signature_ids {
+            outcomes.push(wait_for_outcome(&app, signature_id).await?);
+        }
+        app.queue_command(&Command::StopWorker, &worker.id).await?;
+        anyhow::Ok(outcomes)
+    });
+    listened?;
+
+    for outcome in outcomes? {
+        assert!(matches!(outcome, TaskOutcome::Success(value) if value == "100"));
+    }
+    assert_eq!(MAX_RUNNING.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        app.get_task_state(&signature_ids[0])
+            .await?
+            .map(|info| info.state),
+        Some(TaskState::Success)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_panicking_async_task_does_not_take_down_worker() -> anyhow::Result<()> {
+    let mut app = AsyncApp::new(InMemoryTestBroker::new());
+    app.register_task::<AsyncPanickingTask>();
+    let app = Arc::new(app);
+    let signature_id = app.queue_task::<AsyncPanickingTask>(()).await?;
+
+    let worker = AsyncWorker::new(app.clone()).await?;
+    worker.take_first_task_in_queue().await?;
+
+    assert!(matches!(
+        app.get_task_result(&signature_id).await?.map(|result| result.outcome),
+        Some(TaskOutcome::Panic(message)) if message == "Something went terribly wrong"
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_async_task_is_aborted_at_hard_time_limit() -> anyhow::Result<()> {
+    let mut app = AsyncApp::new(InMemoryTestBroker::new());
+    app.register_task::<AsyncStuckTask>();
+    let app = Arc::new(app);
+
+    let signature_id = app.queue_task::<AsyncStuckTask>(()).await?;
+
+    let worker = AsyncWorker::new(app.clone()).await?;
+    tokio::time::timeout(Duration::from_secs(1), worker.take_first_task_in_queue()).await??;
+
+    assert!(matches!(
+        app.get_task_result(&signature_id)
+            .await?
+            .map(|result| result.outcome),
+        Some(TaskOutcome::TimedOut)
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sync_worker_runs_task_queued_by_async_app() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+
+    let mut async_app = AsyncApp::new(broker.clone());
+    async_app.register_task::<AsyncDoubleTask>();
+
+    let signature_id = async_app.queue_task::<AsyncDoubleTask>(21).await?;
+
+    let mut app = App::with_shared_broker(broker);
+    app.register_task::<DoubleTask>();
+    let app = Arc::new(app);
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(
+        app.async_result::<DoubleTask>(signature_id).try_get()?,
+        Some(Ok(42))
+    );
+
+    Ok(())
+}
@@ -0,0 +1,188 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    broker::TaskState,
+    result::TaskError,
+    task::{Signature, Task},
+    worker::Worker,
+    App, Error,
+};
+use std::sync::Arc;
+
+struct AddOneTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for AddOneTask {
+    type ArgumentType = u32;
+    type ReturnType = u32;
+    type ErrorType = ();
+
+    const ID: &'static str = "AddOneTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg + 1)
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+struct DescribeTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for DescribeTask {
+    type ArgumentType = u32;
+    type ReturnType = String;
+    type ErrorType = String;
+
+    const ID: &'static str = "DescribeTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        if *arg == 0 {
+            return Err("Nothing to describe".to_string());
+        }
+        Ok(format!("The number {arg}"))
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+struct ShoutTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for ShoutTask {
+    type ArgumentType = String;
+    type ReturnType = String;
+    type ErrorType = ();
+
+    const ID: &'static str = "ShoutTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg.to_uppercase())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+fn app() -> Arc<App<InMemoryTestBroker>> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<AddOneTask>();
+    app.register_task::<DescribeTask>();
+    app.register_task::<ShoutTask>();
+    Arc::new(app)
+}
+
+#[test]
+fn test_chain_passes_return_values_along() -> anyhow::Result<()> {
+    let app = app();
+
+    let async_result = app
+        .chain()
+        .then::<AddOneTask>(1)
+        .then::<AddOneTask>()
+        .then::<DescribeTask>()
+        .then::<ShoutTask>()
+        .queue()?;
+    assert_eq!(
+        async_result.state()?.map(|info| info.state),
+        Some(TaskState::Pending)
+    );
+
+    // Each worker run finishes one task in the chain and queues the next.
+    let worker = Worker::new(app.clone())?;
+    for _ in 0..3 {
+        worker.take_first_task_in_queue()?;
+        assert_eq!(async_result.try_get()?, None);
+    }
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(
+        async_result.try_get()?,
+        Some(Ok("THE NUMBER 3".to_string()))
+    );
+    assert!(matches!(
+        worker.take_first_task_in_queue(),
+        Err(Error::NoDueMessage)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_task_fails_rest_of_chain() -> anyhow::Result<()> {
+    let app = app();
+
+    let async_result = app
+        .chain()
+        .then::<DescribeTask>(0)
+        .then::<ShoutTask>()
+        .queue()?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    let Some(Err(TaskError::UpstreamFailed(failed_signature_id))) = async_result.try_get()? else {
+        panic!("Expected the chain to fail upstream");
+    };
+    let failed_result = app.async_result::<DescribeTask>(failed_signature_id);
+    assert_eq!(
+        failed_result.try_get()?,
+        Some(Err(TaskError::Failed("Nothing to describe".to_string())))
+    );
+    assert_eq!(
+        async_result.state()?.map(|info| info.state),
+        Some(TaskState::Failure)
+    );
+    assert!(matches!(
+        worker.take_first_task_in_queue(),
+        Err(Error::NoDueMessage)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_chain_with_unregistered_task_is_not_queued() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<AddOneTask>();
+
+    assert!(matches!(
+        app.chain()
+            .then::<AddOneTask>(1)
+            .then::<DescribeTask>()
+            .queue(),
+        Err(Error::NotRegistered(task_id)) if task_id == "DescribeTask"
+    ));
+
+    Ok(())
+}
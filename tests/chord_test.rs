@@ -0,0 +1,247 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    broker::Broker,
+    messages::{Command, DEFAULT_QUEUE},
+    result::TaskError,
+    task::{Signature, Task},
+    worker::Worker,
+    App,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+struct SquareTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for SquareTask {
+    type ArgumentType = u64;
+    type ReturnType = u64;
+    type ErrorType = String;
+
+    const ID: &'static str = "SquareTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        arg.checked_mul(*arg)
+            .ok_or_else(|| "Square out of range".to_string())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+static SUM_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+struct SumTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for SumTask {
+    type ArgumentType = Vec<u64>;
+    type ReturnType = u64;
+    type ErrorType = ();
+
+    const ID: &'static str = "SumTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        SUM_RUNS.fetch_add(1, Ordering::SeqCst);
+        Ok(arg.iter().sum())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+struct CountTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for CountTask {
+    type ArgumentType = Vec<u64>;
+    type ReturnType = usize;
+    type ErrorType = ();
+
+    const ID: &'static str = "CountTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg.len())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+fn app() -> Arc<App<InMemoryTestBroker>> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<SquareTask>();
+    app.register_task::<SumTask>();
+    app.register_task::<CountTask>();
+    Arc::new(app)
+}
+
+#[test]
+fn test_callback_is_queued_once_when_workers_finish_together() -> anyhow::Result<()> {
+    let app = app();
+
+    let async_result = app
+        .group((1..=40).collect())
+        .chord::<SquareTask, SumTask>()?;
+
+    let listeners = (0..2)
+        .map(|_| {
+            let worker = Worker::builder(app.clone())
+                .concurrency(4)
+                .poll_interval(Duration::from_millis(5))
+                .build()?;
+            let worker_id = worker.id.clone();
+            Ok((
+                worker_id,
+                thread::spawn(move || worker.listen_for_messages()),
+            ))
+        })
+        .collect::<parsnip::Result<Vec<_>>>()?;
+
+    assert_eq!(async_result.wait(Duration::from_secs(5))?, Ok(22140));
+
+    for (worker_id, listener) in listeners {
+        app.queue_command(&Command::StopWorker, &worker_id)?;
+        listener.join().expect("Worker thread panicked")?;
+    }
+    assert_eq!(SUM_RUNS.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_redelivered_header_task_is_counted_once() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SquareTask>();
+    app.register_task::<CountTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.group(vec![2, 3]).chord::<SquareTask, CountTask>()?;
+
+    // Keep a copy of the first header message, to deliver it again as if the
+    // worker died after finishing the task but before acknowledging it.
+    let first_header = broker.queues.read().expect("Failed to aquire lock")[DEFAULT_QUEUE]
+        .front()
+        .cloned()
+        .expect("The header is not queued");
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    broker
+        .queues
+        .write()
+        .expect("Failed to aquire lock")
+        .entry(DEFAULT_QUEUE.to_string())
+        .or_default()
+        .push_front(first_header);
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(async_result.try_get()?, None);
+    assert_eq!(broker.queued_count(), 1);
+
+    worker.take_first_task_in_queue()?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, Some(Ok(2)));
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_header_task_fails_callback() -> anyhow::Result<()> {
+    let app = app();
+
+    let async_result = app
+        .group(vec![2, u64::MAX])
+        .chord::<SquareTask, SumTask>()?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, None);
+    worker.take_first_task_in_queue()?;
+
+    let Some(Err(TaskError::UpstreamFailed(failed_signature_id))) = async_result.try_get()? else {
+        panic!("Expected the callback to fail upstream");
+    };
+    assert_eq!(
+        app.async_result::<SquareTask>(failed_signature_id)
+            .try_get()?,
+        Some(Err(TaskError::Failed("Square out of range".to_string())))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_chord_without_header_runs_callback() -> anyhow::Result<()> {
+    let app = app();
+
+    let async_result = app.group(Vec::new()).chord::<SquareTask, CountTask>()?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, Some(Ok(0)));
+
+    Ok(())
+}
+
+#[test]
+fn test_redelivered_header_task_completes_chord_after_failing_to() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SquareTask>();
+    app.register_task::<CountTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.group(vec![2, 3]).chord::<SquareTask, CountTask>()?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    // Fail to queue the callback once the last header task has finished, and
+    // to put the message back on the queue, as if the broker went away.
+    broker.fail_pushes.store(true, Ordering::SeqCst);
+    assert!(worker.take_first_task_in_queue().is_err());
+    broker.fail_pushes.store(false, Ordering::SeqCst);
+    assert_eq!(broker.queued_count(), 0);
+
+    assert_eq!(broker.requeue_unacked_messages(Duration::ZERO)?, 1);
+    worker.take_first_task_in_queue()?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, Some(Ok(2)));
+    assert_eq!(broker.queued_count(), 0);
+
+    Ok(())
+}
@@ -0,0 +1,670 @@
+#[cfg(feature = "async")]
+use parsnip::broker::AsyncBroker;
+use parsnip::{
+    broker::{Broker, TaskInfo, WorkerInfo},
+    messages::{Command, Message, ResultMessage},
+    task::RateLimit,
+};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, LinkedList, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+
+pub struct Reservation {
+    pub worker_id: String,
+    pub message: Message,
+    pub reserved_at: Instant,
+}
+
+pub struct InMemoryTestBroker {
+    pub task_results: RwLock<HashMap<String, ResultMessage>>,
+    pub task_info: RwLock<HashMap<String, TaskInfo>>,
+    pub queues: RwLock<HashMap<String, VecDeque<Message>>>,
+    /// Entries pushed onto each queue as they are, such as ones that can't
+    /// be deserialized as messages. Taken before the messages on the queue.
+    pub raw_queues: RwLock<HashMap<String, VecDeque<String>>>,
+    /// The reserved entries that couldn't be deserialized as messages.
+    pub dead_letters: RwLock<Vec<String>>,
+    pub delayed: RwLock<Vec<Message>>,
+    pub reserved: RwLock<Vec<Reservation>>,
+    pub revoked: RwLock<HashSet<String>>,
+    /// The signature IDs of the finished tasks in the header of each chord.
+    pub chord_finished: RwLock<HashMap<String, HashSet<String>>>,
+    /// The IDs of the chords whose callback was queued or failed.
+    pub chord_completed: RwLock<HashSet<String>>,
+    /// The tokens left in the rate limit bucket of each task, and when it
+    /// was last refilled.
+    pub rate_limit_buckets: RwLock<HashMap<String, (f64, Instant)>>,
+    /// The signature ID holding each dedup key, by task ID and key.
+    pub dedup_keys: RwLock<HashMap<(String, String), String>>,
+    pub scheduler_lock: RwLock<Option<(String, Instant)>>,
+    pub schedule_last_runs: RwLock<HashMap<String, SystemTime>>,
+    pub command_queues: RwLock<HashMap<String, LinkedList<Command>>>,
+    pub worker_register: RwLock<HashMap<String, WorkerInfo>>,
+    /// When the registration of each worker sending heartbeats expires.
+    pub worker_expiries: RwLock<HashMap<String, Instant>>,
+    /// Makes pushing messages fail, as if the broker was unreachable.
+    pub fail_pushes: AtomicBool,
+}
+
+impl InMemoryTestBroker {
+    pub fn new() -> Self {
+        Self {
+            task_results: RwLock::new(HashMap::new()),
+            task_info: RwLock::new(HashMap::new()),
+            queues: RwLock::new(HashMap::new()),
+            raw_queues: RwLock::new(HashMap::new()),
+            dead_letters: RwLock::new(Vec::new()),
+            delayed: RwLock::new(Vec::new()),
+            reserved: RwLock::new(Vec::new()),
+            revoked: RwLock::new(HashSet::new()),
+            chord_finished: RwLock::new(HashMap::new()),
+            chord_completed: RwLock::new(HashSet::new()),
+            rate_limit_buckets: RwLock::new(HashMap::new()),
+            dedup_keys: RwLock::new(HashMap::new()),
+            scheduler_lock: RwLock::new(None),
+            schedule_last_runs: RwLock::new(HashMap::new()),
+            command_queues: RwLock::new(HashMap::new()),
+            worker_register: RwLock::new(HashMap::new()),
+            worker_expiries: RwLock::new(HashMap::new()),
+            fail_pushes: AtomicBool::new(false),
+        }
+    }
+
+    /// Remove the workers whose last heartbeat has expired from the register.
+    fn expire_workers(&self) {
+        let mut worker_register = self.worker_register.write().expect("Failed to aquire lock");
+        self.worker_expiries
+            .write()
+            .expect("Failed to aquire lock")
+            .retain(|worker_id, expires_at| {
+                let expired = *expires_at <= Instant::now();
+                if expired {
+                    worker_register.remove(worker_id);
+                }
+                !expired
+            });
+    }
+
+    /// Push `entry` onto `queue` as it is, as if it was queued by another
+    /// client.
+    // Not every test binary that includes this module uses it.
+    #[allow(dead_code)]
+    pub fn push_raw_message(&self, queue: &str, entry: &str) {
+        self.raw_queues
+            .write()
+            .expect("Failed to aquire lock")
+            .entry(queue.to_string())
+            .or_default()
+            .push_back(entry.to_string());
+    }
+
+    /// The number of messages waiting on all queues.
+    // Not every test binary that includes this module uses it.
+    #[allow(dead_code)]
+    pub fn queued_count(&self) -> usize {
+        self.queues
+            .read()
+            .expect("Failed to aquire lock")
+            .values()
+            .map(VecDeque::len)
+            .sum()
+    }
+}
+
+impl Broker for InMemoryTestBroker {
+    fn push_message(&self, message: &Message) -> parsnip::Result<()> {
+        if self.fail_pushes.load(Ordering::SeqCst) {
+            return Err(parsnip::Error::broker("Failed to push message"));
+        }
+        self.queues
+            .write()
+            .expect("Failed to aquire lock")
+            .entry(message.queue.clone())
+            .or_default()
+            .push_back(message.clone());
+        Ok(())
+    }
+
+    fn push_delayed_message(&self, message: &Message) -> parsnip::Result<()> {
+        self.delayed
+            .write()
+            .expect("Failed to aquire lock")
+            .push(message.clone());
+        Ok(())
+    }
+
+    fn promote_due_messages(&self, queues: &[String]) -> parsnip::Result<usize> {
+        let mut delayed = self.delayed.write().expect("Failed to aquire lock");
+        let mut all_queues = self.queues.write().expect("Failed to aquire lock");
+
+        let (mut due, not_due): (Vec<_>, Vec<_>) = delayed
+            .drain(..)
+            .partition(|message| message.is_due() && queues.contains(&message.queue));
+        *delayed = not_due;
+
+        due.sort_by_key(|message| message.not_before);
+        let promoted = due.len();
+        for message in due {
+            all_queues
+                .entry(message.queue.clone())
+                .or_default()
+                .push_back(message);
+        }
+        Ok(promoted)
+    }
+
+    fn reserve_message(
+        &self,
+        worker_id: &str,
+        queues: &[String],
+    ) -> parsnip::Result<Option<Message>> {
+        let raw = {
+            let mut raw_queues = self.raw_queues.write().expect("Failed to aquire lock");
+            queues
+                .iter()
+                .find_map(|queue| raw_queues.get_mut(queue)?.pop_front())
+        };
+        let message = if let Some(raw) = raw {
+            match Message::from_json(&raw) {
+                Ok(message) => Some(message),
+                Err(_) => {
+                    self.dead_letters
+                        .write()
+                        .expect("Failed to aquire lock")
+                        .push(raw);
+                    return Ok(None);
+                }
+            }
+        } else {
+            let mut all_queues = self.queues.write().expect("Failed to aquire lock");
+            // Take the highest priority message, and the first queued of
+            // those.
+            queues.iter().find_map(|queue| {
+                let messages = all_queues.get_mut(queue)?;
+                let (index, _) = messages
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(index, message)| (message.priority, Reverse(*index)))?;
+                messages.remove(index)
+            })
+        };
+        if let Some(message) = &message {
+            self.reserved
+                .write()
+                .expect("Failed to aquire lock")
+                .push(Reservation {
+                    worker_id: worker_id.to_string(),
+                    message: message.clone(),
+                    reserved_at: Instant::now(),
+                });
+        }
+        Ok(message)
+    }
+
+    fn ack_message(&self, worker_id: &str, message: &Message) -> parsnip::Result<()> {
+        self.reserved
+            .write()
+            .expect("Failed to aquire lock")
+            .retain(|reservation| {
+                reservation.worker_id != worker_id
+                    || reservation.message.signature_id != message.signature_id
+                    || reservation.message.attempt != message.attempt
+            });
+        Ok(())
+    }
+
+    fn nack_message(&self, worker_id: &str, message: &Message) -> parsnip::Result<()> {
+        Broker::ack_message(self, worker_id, message)?;
+        self.queues
+            .write()
+            .expect("Failed to aquire lock")
+            .entry(message.queue.clone())
+            .or_default()
+            .push_front(message.clone());
+        Ok(())
+    }
+
+    fn requeue_unacked_messages(&self, visibility_timeout: Duration) -> parsnip::Result<usize> {
+        self.expire_workers();
+        let worker_register = self.worker_register.read().expect("Failed to aquire lock");
+        let mut reserved = self.reserved.write().expect("Failed to aquire lock");
+        let mut queues = self.queues.write().expect("Failed to aquire lock");
+
+        let (expired, held): (Vec<_>, Vec<_>) = reserved.drain(..).partition(|reservation| {
+            !worker_register.contains_key(&reservation.worker_id)
+                || reservation.reserved_at.elapsed() > visibility_timeout
+        });
+        *reserved = held;
+
+        let requeued = expired.len();
+        for reservation in expired.into_iter().rev() {
+            queues
+                .entry(reservation.message.queue.clone())
+                .or_default()
+                .push_front(reservation.message);
+        }
+        Ok(requeued)
+    }
+
+    fn claim_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> parsnip::Result<Option<String>> {
+        let mut dedup_keys = self.dedup_keys.write().expect("Failed to aquire lock");
+        match dedup_keys.entry((task_id.to_string(), dedup_key.to_string())) {
+            Entry::Occupied(entry) => Ok(Some(entry.get().clone())),
+            Entry::Vacant(entry) => {
+                entry.insert(signature_id.to_string());
+                Ok(None)
+            }
+        }
+    }
+
+    fn release_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> parsnip::Result<()> {
+        let mut dedup_keys = self.dedup_keys.write().expect("Failed to aquire lock");
+        let key = (task_id.to_string(), dedup_key.to_string());
+        if dedup_keys
+            .get(&key)
+            .is_some_and(|holder| holder == signature_id)
+        {
+            dedup_keys.remove(&key);
+        }
+        Ok(())
+    }
+
+    fn take_rate_limit_token(
+        &self,
+        task_id: &str,
+        rate_limit: &RateLimit,
+    ) -> parsnip::Result<Option<Duration>> {
+        let mut buckets = self
+            .rate_limit_buckets
+            .write()
+            .expect("Failed to aquire lock");
+        let capacity = f64::from(rate_limit.max_runs.get());
+        let tokens_per_second = capacity / rate_limit.per.as_secs_f64();
+        let now = Instant::now();
+
+        let (tokens, refilled_at) = buckets
+            .entry(task_id.to_string())
+            .or_insert((capacity, now));
+        *tokens = (*tokens + (now - *refilled_at).as_secs_f64() * tokens_per_second).min(capacity);
+        *refilled_at = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(None)
+        } else {
+            Ok(Some(Duration::from_secs_f64(
+                (1.0 - *tokens) / tokens_per_second,
+            )))
+        }
+    }
+
+    fn revoke(&self, signature_id: &str) -> parsnip::Result<()> {
+        self.revoked
+            .write()
+            .expect("Failed to aquire lock")
+            .insert(signature_id.to_string());
+        Ok(())
+    }
+
+    fn is_revoked(&self, signature_id: &str) -> parsnip::Result<bool> {
+        Ok(self
+            .revoked
+            .read()
+            .expect("Failed to aquire lock")
+            .contains(signature_id))
+    }
+
+    fn push_command(
+        &self,
+        command: &parsnip::messages::Command,
+        worker_id: &str,
+    ) -> parsnip::Result<()> {
+        if !self
+            .command_queues
+            .read()
+            .expect("Failed to aquire lock")
+            .contains_key(worker_id)
+        {
+            self.command_queues
+                .write()
+                .expect("Failed to aquire lock")
+                .insert(worker_id.to_string(), LinkedList::new());
+        }
+
+        self.command_queues
+            .write()
+            .expect("Failed to aquire lock")
+            .get_mut(worker_id)
+            .expect("Linked list was not initialized when pushing a new command")
+            .push_back(command.clone());
+        Ok(())
+    }
+
+    fn pop_command(&self, worker_id: &str) -> parsnip::Result<Option<parsnip::messages::Command>> {
+        match self
+            .command_queues
+            .write()
+            .expect("Failed to aquire lock")
+            .get_mut(worker_id)
+        {
+            None => Ok(None),
+            Some(ll) => Ok(ll.pop_front()),
+        }
+    }
+
+    fn store_result(&self, result_message: ResultMessage) -> parsnip::Result<()> {
+        self.task_results
+            .write()
+            .expect("Failed to aquire lock")
+            .insert(result_message.signature_id.clone(), result_message);
+        Ok(())
+    }
+
+    fn add_finished_chord_task(
+        &self,
+        chord_id: &str,
+        signature_id: &str,
+    ) -> parsnip::Result<usize> {
+        let mut chord_finished = self.chord_finished.write().expect("Failed to aquire lock");
+        let finished = chord_finished.entry(chord_id.to_string()).or_default();
+        finished.insert(signature_id.to_string());
+        Ok(finished.len())
+    }
+
+    fn is_chord_completed(&self, chord_id: &str) -> parsnip::Result<bool> {
+        Ok(self
+            .chord_completed
+            .read()
+            .expect("Failed to aquire lock")
+            .contains(chord_id))
+    }
+
+    fn set_chord_completed(&self, chord_id: &str) -> parsnip::Result<()> {
+        self.chord_completed
+            .write()
+            .expect("Failed to aquire lock")
+            .insert(chord_id.to_string());
+        Ok(())
+    }
+
+    fn get_result(&self, signature_id: &str) -> parsnip::Result<Option<ResultMessage>> {
+        Ok(self
+            .task_results
+            .read()
+            .expect("Failed to aquire lock")
+            .get(signature_id)
+            .cloned())
+    }
+
+    fn forget_result(&self, signature_id: &str) -> parsnip::Result<()> {
+        self.task_results
+            .write()
+            .expect("Failed to aquire lock")
+            .remove(signature_id);
+        self.task_info
+            .write()
+            .expect("Failed to aquire lock")
+            .remove(signature_id);
+        Ok(())
+    }
+
+    fn update_task_info(&self, info: TaskInfo) -> parsnip::Result<()> {
+        self.task_info
+            .write()
+            .expect("Failed to aquire lock")
+            .insert(info.signature_id.clone(), info);
+        Ok(())
+    }
+
+    fn get_task_info(&self, signature_id: &str) -> parsnip::Result<Option<TaskInfo>> {
+        Ok(self
+            .task_info
+            .read()
+            .expect("Failed to aquire lock")
+            .get(signature_id)
+            .cloned())
+    }
+
+    fn update_worker_info(&self, info: parsnip::broker::WorkerInfo) -> parsnip::Result<()> {
+        self.worker_register
+            .write()
+            .expect("Failed to aquire lock")
+            .insert(info.id.clone(), info);
+        Ok(())
+    }
+
+    fn worker_heartbeat(&self, info: WorkerInfo, ttl: Duration) -> parsnip::Result<()> {
+        self.worker_expiries
+            .write()
+            .expect("Failed to aquire lock")
+            .insert(info.id.clone(), Instant::now() + ttl);
+        Broker::update_worker_info(self, info)
+    }
+
+    fn remove_worker_info(&self, worker_id: &str) -> parsnip::Result<()> {
+        self.worker_register
+            .write()
+            .expect("Failed to aquire lock")
+            .remove(worker_id);
+        self.worker_expiries
+            .write()
+            .expect("Failed to aquire lock")
+            .remove(worker_id);
+        Ok(())
+    }
+
+    fn get_worker_info(
+        &self,
+        worker_id: &str,
+    ) -> parsnip::Result<Option<parsnip::broker::WorkerInfo>> {
+        Ok(self
+            .worker_register
+            .read()
+            .expect("Failed to aquire lock")
+            .get(worker_id)
+            .cloned())
+    }
+
+    fn all_workers(&self) -> parsnip::Result<Option<Vec<parsnip::broker::WorkerInfo>>> {
+        self.expire_workers();
+        Ok(Some(
+            self.worker_register
+                .read()
+                .expect("Failed to aquire lock")
+                .values()
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    fn acquire_scheduler_lock(&self, scheduler_id: &str, ttl: Duration) -> parsnip::Result<bool> {
+        let mut scheduler_lock = self.scheduler_lock.write().expect("Failed to aquire lock");
+        let held_by_other = scheduler_lock.as_ref().is_some_and(|(holder, expires_at)| {
+            holder != scheduler_id && *expires_at > Instant::now()
+        });
+        if held_by_other {
+            return Ok(false);
+        }
+        *scheduler_lock = Some((scheduler_id.to_string(), Instant::now() + ttl));
+        Ok(true)
+    }
+
+    fn release_scheduler_lock(&self, scheduler_id: &str) -> parsnip::Result<()> {
+        let mut scheduler_lock = self.scheduler_lock.write().expect("Failed to aquire lock");
+        if scheduler_lock
+            .as_ref()
+            .is_some_and(|(holder, _)| holder == scheduler_id)
+        {
+            *scheduler_lock = None;
+        }
+        Ok(())
+    }
+
+    fn get_schedule_last_run(&self, name: &str) -> parsnip::Result<Option<SystemTime>> {
+        Ok(self
+            .schedule_last_runs
+            .read()
+            .expect("Failed to aquire lock")
+            .get(name)
+            .copied())
+    }
+
+    fn advance_schedule_last_run(
+        &self,
+        name: &str,
+        previous_run: Option<SystemTime>,
+        last_run: SystemTime,
+        scheduler_id: &str,
+    ) -> parsnip::Result<bool> {
+        let scheduler_lock = self.scheduler_lock.read().expect("Failed to aquire lock");
+        let holds_lock = scheduler_lock.as_ref().is_some_and(|(holder, expires_at)| {
+            holder == scheduler_id && *expires_at > Instant::now()
+        });
+        let mut schedule_last_runs = self
+            .schedule_last_runs
+            .write()
+            .expect("Failed to aquire lock");
+        if !holds_lock || schedule_last_runs.get(name).copied() != previous_run {
+            return Ok(false);
+        }
+        schedule_last_runs.insert(name.to_string(), last_run);
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncBroker for InMemoryTestBroker {
+    async fn push_message(&self, message: &Message) -> parsnip::Result<()> {
+        Broker::push_message(self, message)
+    }
+
+    async fn push_delayed_message(&self, message: &Message) -> parsnip::Result<()> {
+        Broker::push_delayed_message(self, message)
+    }
+
+    async fn promote_due_messages(&self, queues: &[String]) -> parsnip::Result<usize> {
+        Broker::promote_due_messages(self, queues)
+    }
+
+    async fn reserve_message(
+        &self,
+        worker_id: &str,
+        queues: &[String],
+    ) -> parsnip::Result<Option<Message>> {
+        Broker::reserve_message(self, worker_id, queues)
+    }
+
+    async fn ack_message(&self, worker_id: &str, message: &Message) -> parsnip::Result<()> {
+        Broker::ack_message(self, worker_id, message)
+    }
+
+    async fn nack_message(&self, worker_id: &str, message: &Message) -> parsnip::Result<()> {
+        Broker::nack_message(self, worker_id, message)
+    }
+
+    async fn requeue_unacked_messages(
+        &self,
+        visibility_timeout: Duration,
+    ) -> parsnip::Result<usize> {
+        Broker::requeue_unacked_messages(self, visibility_timeout)
+    }
+
+    async fn claim_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> parsnip::Result<Option<String>> {
+        Broker::claim_dedup_key(self, task_id, dedup_key, signature_id)
+    }
+
+    async fn release_dedup_key(
+        &self,
+        task_id: &str,
+        dedup_key: &str,
+        signature_id: &str,
+    ) -> parsnip::Result<()> {
+        Broker::release_dedup_key(self, task_id, dedup_key, signature_id)
+    }
+
+    async fn take_rate_limit_token(
+        &self,
+        task_id: &str,
+        rate_limit: &RateLimit,
+    ) -> parsnip::Result<Option<Duration>> {
+        Broker::take_rate_limit_token(self, task_id, rate_limit)
+    }
+
+    async fn revoke(&self, signature_id: &str) -> parsnip::Result<()> {
+        Broker::revoke(self, signature_id)
+    }
+
+    async fn is_revoked(&self, signature_id: &str) -> parsnip::Result<bool> {
+        Broker::is_revoked(self, signature_id)
+    }
+
+    async fn push_command(&self, command: &Command, worker_id: &str) -> parsnip::Result<()> {
+        Broker::push_command(self, command, worker_id)
+    }
+
+    async fn pop_command(&self, worker_id: &str) -> parsnip::Result<Option<Command>> {
+        Broker::pop_command(self, worker_id)
+    }
+
+    async fn store_result(&self, result_message: ResultMessage) -> parsnip::Result<()> {
+        Broker::store_result(self, result_message)
+    }
+
+    async fn add_finished_chord_task(
+        &self,
+        chord_id: &str,
+        signature_id: &str,
+    ) -> parsnip::Result<usize> {
+        Broker::add_finished_chord_task(self, chord_id, signature_id)
+    }
+
+    async fn is_chord_completed(&self, chord_id: &str) -> parsnip::Result<bool> {
+        Broker::is_chord_completed(self, chord_id)
+    }
+
+    async fn set_chord_completed(&self, chord_id: &str) -> parsnip::Result<()> {
+        Broker::set_chord_completed(self, chord_id)
+    }
+
+    async fn get_result(&self, signature_id: &str) -> parsnip::Result<Option<ResultMessage>> {
+        Broker::get_result(self, signature_id)
+    }
+
+    async fn update_task_info(&self, info: TaskInfo) -> parsnip::Result<()> {
+        Broker::update_task_info(self, info)
+    }
+
+    async fn get_task_info(&self, signature_id: &str) -> parsnip::Result<Option<TaskInfo>> {
+        Broker::get_task_info(self, signature_id)
+    }
+
+    async fn update_worker_info(&self, info: WorkerInfo) -> parsnip::Result<()> {
+        Broker::update_worker_info(self, info)
+    }
+
+    async fn worker_heartbeat(&self, info: WorkerInfo, ttl: Duration) -> parsnip::Result<()> {
+        Broker::worker_heartbeat(self, info, ttl)
+    }
+
+    async fn remove_worker_info(&self, worker_id: &str) -> parsnip::Result<()> {
+        Broker::remove_worker_info(self, worker_id)
+    }
+}
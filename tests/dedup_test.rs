@@ -0,0 +1,144 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    options::QueueOptions,
+    task::{Signature, Task},
+    worker::Worker,
+    App,
+};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+struct ReindexUserTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for ReindexUserTask {
+    type ArgumentType = u64;
+    type ReturnType = u64;
+    type ErrorType = ();
+
+    const ID: &'static str = "ReindexUserTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(*arg)
+    }
+
+    fn dedup_key(arg: &Self::ArgumentType) -> Option<String> {
+        Some(format!("user-{arg}"))
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+struct ReportTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for ReportTask {
+    type ArgumentType = String;
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "ReportTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_duplicate_returns_queued_invocation() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<ReindexUserTask>();
+
+    let first = app.queue_task::<ReindexUserTask>(42)?;
+    let duplicate = app.queue_task::<ReindexUserTask>(42)?;
+    let other_user = app.queue_task::<ReindexUserTask>(7)?;
+
+    assert_eq!(duplicate.id(), first.id());
+    assert_ne!(other_user.id(), first.id());
+    assert_eq!(broker.queued_count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_finished_invocation_releases_dedup_key() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<ReindexUserTask>();
+    let app = Arc::new(app);
+
+    let first = app.queue_task::<ReindexUserTask>(42)?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(first.try_get()?, Some(Ok(42)));
+
+    let second = app.queue_task::<ReindexUserTask>(42)?;
+    assert_ne!(second.id(), first.id());
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_queueing_releases_dedup_key() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<ReindexUserTask>();
+
+    broker.fail_pushes.store(true, Ordering::SeqCst);
+    assert!(app.queue_task::<ReindexUserTask>(42).is_err());
+    assert!(broker
+        .dedup_keys
+        .read()
+        .expect("Failed to aquire lock")
+        .is_empty());
+
+    broker.fail_pushes.store(false, Ordering::SeqCst);
+    app.queue_task::<ReindexUserTask>(42)?;
+    assert_eq!(broker.queued_count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_dedup_key_can_be_given_per_call() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<ReportTask>();
+
+    let options = QueueOptions::new().dedup_key("nightly");
+    let first = app.queue_task_with::<ReportTask>("sales".to_string(), options.clone())?;
+    let duplicate = app.queue_task_with::<ReportTask>("sales".to_string(), options)?;
+    let undeduplicated = app.queue_task::<ReportTask>("sales".to_string())?;
+
+    assert_eq!(duplicate.id(), first.id());
+    assert_ne!(undeduplicated.id(), first.id());
+
+    Ok(())
+}
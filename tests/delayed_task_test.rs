@@ -0,0 +1,107 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    options::QueueOptions,
+    task::{Signature, Task},
+    worker::Worker,
+    App, Error,
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+struct NegateTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for NegateTask {
+    type ArgumentType = i32;
+    type ReturnType = i32;
+    type ErrorType = ();
+
+    const ID: &'static str = "NegateTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(-arg)
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_task_with_countdown_is_not_run_before_it_is_due() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<NegateTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task_with::<NegateTask>(
+        1,
+        QueueOptions::new().countdown(Duration::from_millis(100)),
+    )?;
+
+    // The message waits with the delayed messages, not on the queue.
+    assert_eq!(broker.queued_count(), 0);
+    assert_eq!(
+        broker.delayed.read().expect("Failed to aquire lock").len(),
+        1
+    );
+
+    let worker = Worker::new(app.clone())?;
+    assert!(matches!(
+        worker.take_first_task_in_queue(),
+        Err(Error::NoDueMessage)
+    ));
+    assert_eq!(async_result.try_get()?, None);
+
+    thread::sleep(Duration::from_millis(100));
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, Some(Ok(-1)));
+    assert!(broker
+        .delayed
+        .read()
+        .expect("Failed to aquire lock")
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_delayed_tasks_are_run_in_order_of_eta() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<NegateTask>();
+    let app = Arc::new(app);
+
+    let now = SystemTime::now();
+    let later = app.queue_task_with::<NegateTask>(
+        2,
+        QueueOptions::new().eta(now + Duration::from_millis(20)),
+    )?;
+    let sooner = app.queue_task_with::<NegateTask>(
+        1,
+        QueueOptions::new().eta(now + Duration::from_millis(10)),
+    )?;
+
+    thread::sleep(Duration::from_millis(20));
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(sooner.try_get()?, Some(Ok(-1)));
+    assert_eq!(later.try_get()?, None);
+
+    worker.take_first_task_in_queue()?;
+    assert_eq!(later.try_get()?, Some(Ok(-2)));
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    broker::TaskState,
+    options::QueueOptions,
+    task::{Signature, Task},
+    worker::Worker,
+    App, Error,
+};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+static WARMUP_RUNS: AtomicU32 = AtomicU32::new(0);
+
+/// Only worth running shortly after it is queued.
+struct CacheWarmupTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for CacheWarmupTask {
+    type ArgumentType = ();
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "CacheWarmupTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        WARMUP_RUNS.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_expired_task_is_revoked_instead_of_run() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<CacheWarmupTask>();
+    let app = Arc::new(app);
+
+    let expired = app.queue_task_with::<CacheWarmupTask>(
+        (),
+        QueueOptions::new().expires(Duration::from_millis(10)),
+    )?;
+    let fresh = app.queue_task_with::<CacheWarmupTask>(
+        (),
+        QueueOptions::new().expires(Duration::from_secs(60)),
+    )?;
+
+    thread::sleep(Duration::from_millis(20));
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(WARMUP_RUNS.load(Ordering::SeqCst), 1);
+    assert!(matches!(
+        expired.try_get(),
+        Err(Error::Revoked(signature_id)) if signature_id == expired.id()
+    ));
+    assert_eq!(
+        expired.state()?.map(|info| info.state),
+        Some(TaskState::Revoked)
+    );
+    assert_eq!(fresh.try_get()?, Some(Ok(())));
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    result::TaskError,
+    task::{Signature, Task},
+    worker::Worker,
+    App, Error,
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+struct DivisionTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for DivisionTask {
+    type ArgumentType = (usize, usize);
+    type ReturnType = usize;
+    type ErrorType = String;
+
+    const ID: &'static str = "DivisionTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        let (numerator, denominator) = *arg;
+        numerator
+            .checked_div(denominator)
+            .ok_or_else(|| "Division by zero".to_string())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_group_collects_results_in_order() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<DivisionTask>();
+    let app = Arc::new(app);
+
+    let group_result = app
+        .group(vec![(6, 3), (6, 0), (6, 2)])
+        .queue::<DivisionTask>()?;
+    assert_eq!(group_result.len(), 3);
+    assert_eq!(group_result.completed_count()?, 0);
+    assert_eq!(group_result.try_get()?, None);
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(group_result.completed_count()?, 1);
+    assert!(!group_result.failed()?);
+
+    worker.take_first_task_in_queue()?;
+    assert!(group_result.failed()?);
+    assert_eq!(group_result.try_get()?, None);
+
+    worker.take_first_task_in_queue()?;
+    assert_eq!(group_result.completed_count()?, 3);
+    assert_eq!(
+        group_result.try_get()?,
+        Some(vec![
+            Ok(2),
+            Err(TaskError::Failed("Division by zero".to_string())),
+            Ok(3),
+        ])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_waiting_for_group() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<DivisionTask>();
+    let app = Arc::new(app);
+
+    let group_result = app
+        .group((1..=4).map(|denominator| (12, denominator)).collect())
+        .queue::<DivisionTask>()?;
+
+    assert!(matches!(
+        group_result.wait(Duration::from_millis(20)),
+        Err(Error::Timeout(signature_id)) if signature_id == group_result.ids()[0]
+    ));
+    assert!(matches!(
+        group_result.wait(Duration::ZERO),
+        Err(Error::Timeout(signature_id)) if signature_id == group_result.ids()[0]
+    ));
+
+    let worker = Worker::new(app.clone())?;
+    let runner = thread::spawn(move || -> parsnip::Result<()> {
+        for _ in 0..4 {
+            worker.take_first_task_in_queue()?;
+        }
+        Ok(())
+    });
+
+    assert_eq!(
+        group_result.wait(Duration::from_secs(5))?,
+        vec![Ok(12), Ok(6), Ok(4), Ok(3)]
+    );
+    runner.join().expect("Runner thread panicked")?;
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    result::{FailedTask, TaskError},
+    task::{Signature, Task},
+    worker::Worker,
+    App, Error,
+};
+use std::sync::{Arc, Mutex};
+
+struct DivisionTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for DivisionTask {
+    type ArgumentType = (usize, usize);
+    type ReturnType = usize;
+    type ErrorType = String;
+
+    const ID: &'static str = "DivisionTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        let (numerator, denominator) = *arg;
+        numerator
+            .checked_div(denominator)
+            .ok_or_else(|| "Division by zero".to_string())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+static NOTIFIED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// Records the quotients it is called with.
+struct NotifyTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for NotifyTask {
+    type ArgumentType = usize;
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "NotifyTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        NOTIFIED.lock().expect("Failed to aquire lock").push(*arg);
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+static COMPENSATED: Mutex<Vec<FailedTask<String>>> = Mutex::new(Vec::new());
+
+/// Records the failed divisions it is called with.
+struct CompensateTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for CompensateTask {
+    type ArgumentType = FailedTask<String>;
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "CompensateTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        COMPENSATED
+            .lock()
+            .expect("Failed to aquire lock")
+            .push(arg.clone());
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+fn app() -> Arc<App<InMemoryTestBroker>> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<DivisionTask>();
+    app.register_task::<NotifyTask>();
+    app.register_task::<CompensateTask>();
+    Arc::new(app)
+}
+
+#[test]
+fn test_success_callback_gets_return_value() -> anyhow::Result<()> {
+    let app = app();
+
+    let async_result = app
+        .signature::<DivisionTask>((42, 6))
+        .link::<NotifyTask>()
+        .link_error::<CompensateTask>()
+        .queue()?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, Some(Ok(7)));
+    worker.take_first_task_in_queue()?;
+
+    assert!(NOTIFIED.lock().expect("Failed to aquire lock").contains(&7));
+    assert!(!COMPENSATED
+        .lock()
+        .expect("Failed to aquire lock")
+        .iter()
+        .any(|failed_task| failed_task.signature_id == async_result.id()));
+
+    Ok(())
+}
+
+#[test]
+fn test_error_callback_gets_error_and_signature_id() -> anyhow::Result<()> {
+    let app = app();
+
+    let async_result = app
+        .signature::<DivisionTask>((1, 0))
+        .link::<NotifyTask>()
+        .link_error::<CompensateTask>()
+        .queue()?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    worker.take_first_task_in_queue()?;
+    assert!(matches!(
+        worker.take_first_task_in_queue(),
+        Err(Error::NoDueMessage)
+    ));
+
+    assert!(COMPENSATED
+        .lock()
+        .expect("Failed to aquire lock")
+        .contains(&FailedTask {
+            signature_id: async_result.id().to_string(),
+            error: TaskError::Failed("Division by zero".to_string()),
+        }));
+
+    Ok(())
+}
+
+#[test]
+fn test_unregistered_callback_is_not_queued() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<DivisionTask>();
+
+    assert!(matches!(
+        app.signature::<DivisionTask>((1, 1))
+            .link::<NotifyTask>()
+            .queue(),
+        Err(Error::NotRegistered(task_id)) if task_id == "NotifyTask"
+    ));
+
+    Ok(())
+}
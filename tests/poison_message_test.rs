@@ -0,0 +1,181 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    messages::Command,
+    result::TaskError,
+    task::{Signature, Task},
+    worker::Worker,
+    App,
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+struct CountWordsTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for CountWordsTask {
+    type ArgumentType = String;
+    type ReturnType = usize;
+    type ErrorType = ();
+
+    const ID: &'static str = "CountWordsTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg.split_whitespace().count())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+/// Only registered with the app queueing tasks, so unknown to the worker.
+struct UnknownTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for UnknownTask {
+    type ArgumentType = ();
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "UnknownTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+/// Has the same ID as `CountWordsTask`, but a different argument type, so
+/// the worker can't deserialize its signature.
+struct MismatchedCountWordsTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for MismatchedCountWordsTask {
+    type ArgumentType = u64;
+    type ReturnType = usize;
+    type ErrorType = ();
+
+    const ID: &'static str = "CountWordsTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(0)
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+fn worker_app(broker: Arc<InMemoryTestBroker>) -> Arc<App<InMemoryTestBroker>> {
+    let mut app = App::with_shared_broker(broker);
+
+    app.register_task::<CountWordsTask>();
+    Arc::new(app)
+}
+
+#[test]
+fn test_unknown_task_does_not_block_later_messages() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut producer = App::with_shared_broker(broker.clone());
+
+    producer.register_task::<UnknownTask>();
+    producer.register_task::<CountWordsTask>();
+    let app = worker_app(broker.clone());
+
+    let unknown = producer.queue_task::<UnknownTask>(())?;
+    let count = producer.queue_task::<CountWordsTask>("a poisoned queue".to_string())?;
+
+    let worker = Worker::builder(app.clone())
+        .poll_interval(Duration::from_millis(10))
+        .build()?;
+    let worker_id = worker.id.clone();
+    let listener = thread::spawn(move || worker.listen_for_messages());
+
+    assert_eq!(count.wait(Duration::from_secs(5))?, Ok(3));
+    assert!(matches!(
+        unknown.wait(Duration::from_secs(5))?,
+        Err(TaskError::Rejected(_))
+    ));
+
+    app.queue_command(&Command::StopWorker, &worker_id)?;
+    listener.join().expect("Worker thread panicked")?;
+    assert_eq!(broker.queued_count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_undeserializable_signature_is_rejected() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut producer = App::with_shared_broker(broker.clone());
+
+    producer.register_task::<MismatchedCountWordsTask>();
+    let app = worker_app(broker.clone());
+
+    let mismatched = producer.queue_task::<MismatchedCountWordsTask>(42)?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    assert!(matches!(
+        mismatched.try_get()?,
+        Some(Err(TaskError::Rejected(_)))
+    ));
+    assert_eq!(broker.queued_count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_undecodable_message_does_not_stop_the_worker() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let app = worker_app(broker.clone());
+
+    broker.push_raw_message("default", "not a message");
+    let count = app.queue_task::<CountWordsTask>("still running".to_string())?;
+
+    let worker = Worker::builder(app.clone())
+        .poll_interval(Duration::from_millis(10))
+        .build()?;
+    let worker_id = worker.id.clone();
+    let listener = thread::spawn(move || worker.listen_for_messages());
+
+    assert_eq!(count.wait(Duration::from_secs(5))?, Ok(2));
+
+    app.queue_command(&Command::StopWorker, &worker_id)?;
+    listener.join().expect("Worker thread panicked")?;
+    assert_eq!(
+        *broker.dead_letters.read().expect("Failed to aquire lock"),
+        vec!["not a message".to_string()]
+    );
+
+    Ok(())
+}
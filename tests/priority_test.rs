@@ -0,0 +1,138 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    messages::{DEFAULT_QUEUE, MAX_PRIORITY},
+    options::QueueOptions,
+    task::{Signature, Task},
+    worker::Worker,
+    App,
+};
+use std::sync::Arc;
+
+struct ResizeTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for ResizeTask {
+    type ArgumentType = u32;
+    type ReturnType = u32;
+    type ErrorType = ();
+
+    const ID: &'static str = "ResizeTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg / 2)
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+struct ThumbnailTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for ThumbnailTask {
+    type ArgumentType = u32;
+    type ReturnType = u32;
+    type ErrorType = ();
+
+    const ID: &'static str = "ThumbnailTask";
+    const PRIORITY: u8 = 5;
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg / 10)
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+fn app() -> Arc<App<InMemoryTestBroker>> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<ResizeTask>();
+    app.register_task::<ThumbnailTask>();
+    Arc::new(app)
+}
+
+#[test]
+fn test_task_priority_is_run_first() -> anyhow::Result<()> {
+    let app = app();
+
+    let resize = app.queue_task::<ResizeTask>(800)?;
+    let thumbnail = app.queue_task::<ThumbnailTask>(800)?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(thumbnail.try_get()?, Some(Ok(80)));
+    assert_eq!(resize.try_get()?, None);
+
+    worker.take_first_task_in_queue()?;
+    assert_eq!(resize.try_get()?, Some(Ok(400)));
+
+    Ok(())
+}
+
+#[test]
+fn test_priority_can_be_overridden_per_call() -> anyhow::Result<()> {
+    let app = app();
+
+    let thumbnail = app.queue_task::<ThumbnailTask>(800)?;
+    let urgent_resize = app.queue_task_with::<ResizeTask>(600, QueueOptions::new().priority(9))?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(urgent_resize.try_get()?, Some(Ok(300)));
+    assert_eq!(thumbnail.try_get()?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_same_priority_is_first_in_first_out() -> anyhow::Result<()> {
+    let app = app();
+
+    let first = app.queue_task::<ResizeTask>(100)?;
+    let second = app.queue_task::<ResizeTask>(200)?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(first.try_get()?, Some(Ok(50)));
+    assert_eq!(second.try_get()?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_priority_is_clamped_to_max_priority() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<ResizeTask>();
+    app.queue_task_with::<ResizeTask>(600, QueueOptions::new().priority(200))?;
+
+    let priorities: Vec<u8> = broker.queues.read().expect("Failed to aquire lock")[DEFAULT_QUEUE]
+        .iter()
+        .map(|message| message.priority)
+        .collect();
+    assert_eq!(priorities, vec![MAX_PRIORITY]);
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    options::QueueOptions,
+    task::{Signature, Task},
+    worker::Worker,
+    App, Error,
+};
+use std::sync::Arc;
+
+struct ReportTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for ReportTask {
+    type ArgumentType = String;
+    type ReturnType = usize;
+    type ErrorType = ();
+
+    const ID: &'static str = "ReportTask";
+    const QUEUE: &'static str = "reports";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg.len())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+fn app() -> Arc<App<InMemoryTestBroker>> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<ReportTask>();
+    Arc::new(app)
+}
+
+#[test]
+fn test_task_is_sent_to_its_declared_queue() -> anyhow::Result<()> {
+    let app = app();
+
+    let async_result = app.queue_task::<ReportTask>("monthly".to_string())?;
+
+    let default_worker = Worker::new(app.clone())?;
+    assert!(matches!(
+        default_worker.take_first_task_in_queue(),
+        Err(Error::NoDueMessage)
+    ));
+
+    let reports_worker = Worker::builder(app.clone()).queues(&["reports"]).build()?;
+    reports_worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, Some(Ok(7)));
+
+    Ok(())
+}
+
+#[test]
+fn test_queue_can_be_overridden_per_call() -> anyhow::Result<()> {
+    let app = app();
+
+    let async_result = app
+        .queue_task_with::<ReportTask>("daily".to_string(), QueueOptions::new().queue("urgent"))?;
+
+    let reports_worker = Worker::builder(app.clone()).queues(&["reports"]).build()?;
+    assert!(matches!(
+        reports_worker.take_first_task_in_queue(),
+        Err(Error::NoDueMessage)
+    ));
+
+    let urgent_worker = Worker::builder(app.clone()).queues(&["urgent"]).build()?;
+    urgent_worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, Some(Ok(5)));
+
+    Ok(())
+}
+
+#[test]
+fn test_worker_consumes_queues_in_priority_order() -> anyhow::Result<()> {
+    let app = app();
+
+    let low = app.queue_task::<ReportTask>("yearly".to_string())?;
+    let high = app
+        .queue_task_with::<ReportTask>("hourly".to_string(), QueueOptions::new().queue("urgent"))?;
+
+    let worker = Worker::builder(app.clone())
+        .queues(&["urgent", "reports"])
+        .build()?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(high.try_get()?, Some(Ok(6)));
+    assert_eq!(low.try_get()?, None);
+
+    worker.take_first_task_in_queue()?;
+    assert_eq!(low.try_get()?, Some(Ok(6)));
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    broker::TaskState,
+    task::{RateLimit, Signature, Task},
+    worker::Worker,
+    App, Error,
+};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+struct GeocodeTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for GeocodeTask {
+    type ArgumentType = String;
+    type ReturnType = usize;
+    type ErrorType = ();
+
+    const ID: &'static str = "GeocodeTask";
+    const RATE_LIMIT: Option<RateLimit> = Some(RateLimit::per_second(NonZeroU32::new(2).unwrap()));
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg.len())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+struct SendSmsTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for SendSmsTask {
+    type ArgumentType = String;
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "SendSmsTask";
+    const RATE_LIMIT: Option<RateLimit> = Some(RateLimit::per_minute(NonZeroU32::MIN));
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_task_over_rate_limit_is_delayed() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<GeocodeTask>();
+    let app = Arc::new(app);
+
+    let async_results = ["Oslo", "Bergen", "Tromsø"]
+        .into_iter()
+        .map(|city| app.queue_task::<GeocodeTask>(city.to_string()))
+        .collect::<parsnip::Result<Vec<_>>>()?;
+
+    let worker = Worker::new(app.clone())?;
+    for _ in &async_results {
+        worker.take_first_task_in_queue()?;
+    }
+    assert_eq!(async_results[0].try_get()?, Some(Ok(4)));
+    assert_eq!(async_results[1].try_get()?, Some(Ok(6)));
+    assert_eq!(async_results[2].try_get()?, None);
+    assert_eq!(
+        async_results[2].state()?.map(|info| info.state),
+        Some(TaskState::Pending)
+    );
+    assert_eq!(
+        broker.delayed.read().expect("Failed to aquire lock").len(),
+        1
+    );
+
+    // A token is added every half second.
+    thread::sleep(Duration::from_millis(600));
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_results[2].try_get()?, Some(Ok(7)));
+
+    Ok(())
+}
+
+#[test]
+fn test_rate_limit_is_shared_between_workers() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SendSmsTask>();
+    let app = Arc::new(app);
+
+    let first = app.queue_task::<SendSmsTask>("Hello".to_string())?;
+    let second = app.queue_task::<SendSmsTask>("Hello again".to_string())?;
+
+    let worker = Worker::new(app.clone())?;
+    let other_worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    other_worker.take_first_task_in_queue()?;
+
+    assert_eq!(first.try_get()?, Some(Ok(())));
+    assert_eq!(second.try_get()?, None);
+
+    let delayed = broker.delayed.read().expect("Failed to aquire lock");
+    let not_before = delayed[0].not_before.expect("Message is not delayed");
+    assert!(not_before > SystemTime::now() + Duration::from_secs(50));
+
+    Ok(())
+}
+
+#[test]
+fn test_rate_limit_without_runs_is_rejected() -> anyhow::Result<()> {
+    assert!(matches!(
+        RateLimit::new(0, Duration::from_secs(1)),
+        Err(Error::InvalidRateLimit(_))
+    ));
+    assert_eq!(
+        RateLimit::new(2, Duration::from_secs(1))?,
+        RateLimit::per_second(NonZeroU32::new(2).unwrap())
+    );
+
+    Ok(())
+}
@@ -0,0 +1,200 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    broker::{Broker, WorkerInfo, WorkerState},
+    messages::{Command, DEFAULT_QUEUE},
+    task::{Signature, Task},
+    worker::Worker,
+    App,
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct SquareTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for SquareTask {
+    type ArgumentType = u32;
+    type ReturnType = u32;
+    type ErrorType = ();
+
+    const ID: &'static str = "SquareTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg * arg)
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_handled_message_is_acknowledged() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SquareTask>();
+    let app = Arc::new(app);
+
+    app.queue_task::<SquareTask>(3)?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    assert!(broker
+        .reserved
+        .read()
+        .expect("Failed to aquire lock")
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_message_held_by_dead_worker_is_requeued() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SquareTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<SquareTask>(3)?;
+
+    // Simulate a worker that reserved the message and then died before
+    // acknowledging it, without ever being registered.
+    broker.reserve_message("dead-worker", &[DEFAULT_QUEUE.to_string()])?;
+    assert_eq!(broker.queued_count(), 0);
+
+    assert_eq!(app.requeue_unacked_messages(Duration::from_secs(60))?, 1);
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(async_result.try_get()?, Some(Ok(9)));
+
+    Ok(())
+}
+
+#[test]
+fn test_worker_without_heartbeat_is_assumed_dead() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SquareTask>();
+    let app = Arc::new(app);
+
+    app.queue_task::<SquareTask>(3)?;
+
+    // Simulate a running worker that reserved the message and then crashed,
+    // so that it stopped sending heartbeats without unregistering.
+    let info = WorkerInfo {
+        id: "crashed-worker".to_string(),
+        state: WorkerState::Running,
+    };
+    broker.worker_heartbeat(info, Duration::from_millis(50))?;
+    broker.reserve_message("crashed-worker", &[DEFAULT_QUEUE.to_string()])?;
+
+    assert_eq!(app.requeue_unacked_messages(Duration::from_secs(60))?, 0);
+    assert_eq!(app.list_workers()?.map(|workers| workers.len()), Some(1));
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(app.requeue_unacked_messages(Duration::from_secs(60))?, 1);
+    assert_eq!(app.list_workers()?.map(|workers| workers.len()), Some(0));
+    assert_eq!(broker.queued_count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_message_held_past_visibility_timeout_is_requeued() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SquareTask>();
+    let app = Arc::new(app);
+
+    app.queue_task::<SquareTask>(3)?;
+
+    let worker = Worker::new(app.clone())?;
+    broker.reserve_message(&worker.id, &[DEFAULT_QUEUE.to_string()])?;
+
+    assert_eq!(app.requeue_unacked_messages(Duration::from_secs(60))?, 0);
+    assert_eq!(app.requeue_unacked_messages(Duration::ZERO)?, 1);
+    assert_eq!(broker.queued_count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_blocking_reserve_waits_on_empty_queue() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SquareTask>();
+    let app = Arc::new(app);
+
+    let worker = Worker::builder(app.clone())
+        .poll_interval(Duration::from_millis(20))
+        .build()?;
+
+    let started = Instant::now();
+    assert!(broker
+        .reserve_message_blocking(
+            &worker.id,
+            &[DEFAULT_QUEUE.to_string()],
+            Duration::from_millis(20),
+        )?
+        .is_none());
+    assert!(started.elapsed() >= Duration::from_millis(20));
+
+    app.queue_task::<SquareTask>(3)?;
+    assert!(broker
+        .reserve_message_blocking(
+            &worker.id,
+            &[DEFAULT_QUEUE.to_string()],
+            Duration::from_millis(20),
+        )?
+        .is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_blocking_reserve_with_zero_timeout_does_not_block() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SquareTask>();
+    let app = Arc::new(app);
+
+    let started = Instant::now();
+    assert!(broker
+        .reserve_message_blocking("worker", &[DEFAULT_QUEUE.to_string()], Duration::ZERO)?
+        .is_none());
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    let worker = Worker::builder(app.clone())
+        .poll_interval(Duration::ZERO)
+        .build()?;
+    let worker_id = worker.id.clone();
+    let listener = thread::spawn(move || worker.listen_for_messages());
+
+    let async_result = app.queue_task::<SquareTask>(3)?;
+    assert_eq!(async_result.wait(Duration::from_secs(5))?, Ok(9));
+
+    app.queue_command(&Command::StopWorker, &worker_id)?;
+    listener.join().expect("Worker thread panicked")?;
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    broker::TaskState,
+    result::TaskError,
+    task::{Backoff, RetryPolicy, Signature, Task},
+    worker::Worker,
+    App,
+};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+static FLAKY_TASK_RUNS: AtomicU32 = AtomicU32::new(0);
+
+/// Fails the first two times it is run, then succeeds.
+struct FlakyTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for FlakyTask {
+    type ArgumentType = ();
+    type ReturnType = u32;
+    type ErrorType = String;
+
+    const ID: &'static str = "FlakyTask";
+
+    const RETRY_POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        backoff: Backoff::Fixed(Duration::ZERO),
+        jitter: false,
+    };
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        let runs = FLAKY_TASK_RUNS.fetch_add(1, Ordering::SeqCst) + 1;
+        if runs < 3 {
+            Err("Downstream service unavailable".to_string())
+        } else {
+            Ok(runs)
+        }
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+/// Always fails, but only with errors that are not worth retrying.
+struct UnretryableTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for UnretryableTask {
+    type ArgumentType = ();
+    type ReturnType = ();
+    type ErrorType = String;
+
+    const ID: &'static str = "UnretryableTask";
+
+    const RETRY_POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: 5,
+        backoff: Backoff::Fixed(Duration::ZERO),
+        jitter: false,
+    };
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Err("Invalid input".to_string())
+    }
+
+    fn is_retryable(error: &Self::ErrorType) -> bool {
+        error != "Invalid input"
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_failed_task_is_retried_until_it_succeeds() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<FlakyTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<FlakyTask>(())?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, None);
+    assert_eq!(
+        async_result.state()?.map(|info| info.state),
+        Some(TaskState::Retrying)
+    );
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, None);
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(async_result.try_get()?, Some(Ok(3)));
+    assert_eq!(broker.queued_count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_unretryable_error_is_stored_immediately() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<UnretryableTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<UnretryableTask>(())?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(
+        async_result.try_get()?,
+        Some(Err(TaskError::Failed("Invalid input".to_string())))
+    );
+    assert_eq!(
+        async_result.state()?.map(|info| info.state),
+        Some(TaskState::Failure)
+    );
+    assert_eq!(broker.queued_count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_exponential_backoff_is_capped() {
+    let policy = RetryPolicy {
+        max_attempts: 10,
+        backoff: Backoff::Exponential {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+        },
+        jitter: false,
+    };
+
+    assert_eq!(policy.delay_after_attempt(1), Duration::from_secs(1));
+    assert_eq!(policy.delay_after_attempt(3), Duration::from_secs(4));
+    assert_eq!(policy.delay_after_attempt(5), Duration::from_secs(10));
+    assert_eq!(policy.delay_after_attempt(100), Duration::from_secs(10));
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    broker::TaskState,
+    context::TaskContext,
+    messages::Command,
+    task::{Signature, Task},
+    worker::Worker,
+    App, Error,
+};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+static SEND_EMAIL_RUNS: AtomicU32 = AtomicU32::new(0);
+
+struct SendEmailTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for SendEmailTask {
+    type ArgumentType = String;
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "SendEmailTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        SEND_EMAIL_RUNS.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+/// Runs until it is told to stop, or for five seconds.
+struct LongRunningTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for LongRunningTask {
+    type ArgumentType = ();
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "LongRunningTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        let context = TaskContext::current().expect("No context for the running task");
+        let started = Instant::now();
+        while !context.is_cancelled() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_revoked_task_is_skipped() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<SendEmailTask>();
+    let app = Arc::new(app);
+
+    let revoked = app.queue_task::<SendEmailTask>("Ignore this".to_string())?;
+    let kept = app.queue_task::<SendEmailTask>("Hello".to_string())?;
+    app.revoke(revoked.id(), false)?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(SEND_EMAIL_RUNS.load(Ordering::SeqCst), 1);
+    assert!(matches!(revoked.try_get(), Err(Error::Revoked(_))));
+    assert_eq!(
+        revoked.state()?.map(|info| info.state),
+        Some(TaskState::Revoked)
+    );
+    assert_eq!(kept.try_get()?, Some(Ok(())));
+
+    Ok(())
+}
+
+#[test]
+fn test_running_task_is_terminated() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<LongRunningTask>();
+    let app = Arc::new(app);
+
+    let worker = Worker::builder(app.clone())
+        .poll_interval(Duration::from_millis(10))
+        .build()?;
+    let worker_id = worker.id.clone();
+    let listener = thread::spawn(move || worker.listen_for_messages());
+
+    let async_result = app.queue_task::<LongRunningTask>(())?;
+    while async_result.state()?.map(|info| info.state) != Some(TaskState::Started) {
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    let started = Instant::now();
+    app.revoke(async_result.id(), true)?;
+    assert!(matches!(
+        async_result.wait(Duration::from_secs(5)),
+        Err(Error::Revoked(_))
+    ));
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(
+        async_result.state()?.map(|info| info.state),
+        Some(TaskState::Revoked)
+    );
+
+    app.queue_command(&Command::StopWorker, &worker_id)?;
+    listener.join().expect("Worker thread panicked")?;
+
+    Ok(())
+}
@@ -1,161 +1,84 @@
-use anyhow;
+mod common;
+
+use common::InMemoryTestBroker;
 use parsnip::{
     self,
-    broker::{Broker, WorkerInfo},
-    messages::ResultMessage,
-    messages::{Command, Message},
+    broker::{Broker, TaskState},
+    messages::{Message, TaskOutcome, DEFAULT_QUEUE},
+    result::TaskError,
     task::Signature,
     task::Task,
     worker::Worker,
-    App,
+    App, Error,
 };
-use serde_json;
-use std::collections::{HashMap, LinkedList};
-use std::sync::RwLock;
-
-struct InMemoryTestBroker {
-    pub task_results: RwLock<HashMap<String, ResultMessage>>,
-    pub queue: RwLock<LinkedList<Message>>,
-    pub command_queues: RwLock<HashMap<String, LinkedList<Command>>>,
-    pub worker_register: RwLock<HashMap<String, WorkerInfo>>,
-}
+use std::sync::Arc;
+use std::time::Duration;
 
-impl InMemoryTestBroker {
-    fn new() -> Self {
-        Self {
-            task_results: RwLock::new(HashMap::new()),
-            queue: RwLock::new(LinkedList::new()),
-            command_queues: RwLock::new(HashMap::new()),
-            worker_register: RwLock::new(HashMap::new()),
-        }
-    }
+struct SummationTask {
+    called_with_signature: Signature<Self>,
 }
 
-impl Broker for InMemoryTestBroker {
-    fn push_message(&self, message: &Message) -> anyhow::Result<()> {
-        self.queue
-            .write()
-            .expect("Failed to aquire lock")
-            .push_back(message.clone());
-        Ok(())
-    }
+impl Task for SummationTask {
+    type ArgumentType = Vec<usize>;
+    type ReturnType = usize;
+    type ErrorType = ();
 
-    fn pop_message(&self) -> anyhow::Result<Option<Message>> {
-        match self
-            .queue
-            .write()
-            .expect("Failed to aquire lock")
-            .pop_front()
-        {
-            Some(message) => Ok(Some(message)),
-            None => Ok(None),
-        }
-    }
+    const ID: &'static str = "SummationTask";
 
-    fn push_command(
-        &self,
-        command: &parsnip::messages::Command,
-        worker_id: &str,
-    ) -> anyhow::Result<()> {
-        if !self
-            .command_queues
-            .read()
-            .expect("Failed to aquire lock")
-            .contains_key(worker_id)
-        {
-            self.command_queues
-                .write()
-                .expect("Failed to aquire lock")
-                .insert(worker_id.to_string(), LinkedList::new());
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
         }
-
-        self.command_queues
-            .write()
-            .expect("Failed to aquire lock")
-            .get_mut(worker_id)
-            .expect("Linked list was not initialized when pushing a new command")
-            .push_back(command.clone());
-        Ok(())
     }
 
-    fn pop_command(&self, worker_id: &str) -> anyhow::Result<Option<parsnip::messages::Command>> {
-        match self
-            .command_queues
-            .write()
-            .expect("Failed to aquire lock")
-            .get_mut(worker_id)
-        {
-            None => Ok(None),
-            Some(ll) => Ok(ll.pop_front()),
-        }
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(arg.iter().sum())
     }
 
-    fn store_result(&self, result_message: ResultMessage) -> anyhow::Result<()> {
-        self.task_results
-            .write()
-            .expect("Failed to aquire lock")
-            .insert(result_message.signature_id.clone(), result_message);
-        Ok(())
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
     }
+}
 
-    fn get_result(&self, signature_id: &str) -> anyhow::Result<Option<ResultMessage>> {
-        Ok(self
-            .task_results
-            .read()
-            .expect("Failed to aquire lock")
-            .get(signature_id)
-            .map(ResultMessage::clone))
-    }
+struct DivisionTask {
+    called_with_signature: Signature<Self>,
+}
 
-    fn update_worker_info(&self, info: parsnip::broker::WorkerInfo) -> anyhow::Result<()> {
-        self.worker_register
-            .write()
-            .expect("Failed to aquire lock")
-            .insert(info.id.clone(), info);
-        Ok(())
-    }
+impl Task for DivisionTask {
+    type ArgumentType = (usize, usize);
+    type ReturnType = usize;
+    type ErrorType = String;
 
-    fn remove_worker_info(&self, worker_id: &str) -> anyhow::Result<()> {
-        self.worker_register
-            .write()
-            .expect("Failed to aquire lock")
-            .remove(worker_id);
-        Ok(())
+    const ID: &'static str = "DivisionTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
     }
 
-    fn get_worker_info(
-        &self,
-        worker_id: &str,
-    ) -> anyhow::Result<Option<parsnip::broker::WorkerInfo>> {
-        Ok(self
-            .worker_register
-            .read()
-            .expect("Failed to aquire lock")
-            .get(worker_id)
-            .map(WorkerInfo::clone))
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        let (numerator, denominator) = *arg;
+        numerator
+            .checked_div(denominator)
+            .ok_or_else(|| "Division by zero".to_string())
     }
 
-    fn all_workers(&self) -> anyhow::Result<Option<Vec<parsnip::broker::WorkerInfo>>> {
-        Ok(Some(
-            self.worker_register
-                .read()
-                .expect("Failed to aquire lock")
-                .values()
-                .map(|v| v.clone())
-                .collect(),
-        ))
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
     }
 }
 
-struct SummationTask {
+struct PanickingTask {
     called_with_signature: Signature<Self>,
 }
 
-impl Task for SummationTask {
-    type ArgumentType = Vec<usize>;
-    type ReturnType = usize;
+impl Task for PanickingTask {
+    type ArgumentType = ();
+    type ReturnType = ();
+    type ErrorType = ();
 
-    const ID: &'static str = "SummationTask";
+    const ID: &'static str = "PanickingTask";
 
     fn from_signature(signature: Signature<Self>) -> Self {
         Self {
@@ -163,8 +86,8 @@ impl Task for SummationTask {
         }
     }
 
-    fn run(arg: &Self::ArgumentType) -> Self::ReturnType {
-        arg.iter().sum()
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        panic!("Something went terribly wrong")
     }
 
     fn signature(&self) -> &Signature<Self> {
@@ -174,19 +97,16 @@ impl Task for SummationTask {
 
 #[test]
 fn test_running_task_from_message() -> anyhow::Result<()> {
-    let mut broker = InMemoryTestBroker::new();
-    let mut app = App::new(&mut broker);
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
 
     app.register_task::<SummationTask>();
+    let app = Arc::new(app);
 
     app.queue_task::<SummationTask>(vec![1, 2, 3])?;
 
-    // Scope because the worker uses the mutable borrow of the broker above
-    // when it is dropped.
-    {
-        let worker = Worker::new(&app)?;
-        worker.take_first_task_in_queue()?;
-    }
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
 
     assert_eq!(
         broker
@@ -198,9 +118,220 @@ fn test_running_task_from_message() -> anyhow::Result<()> {
     );
     let task_results = broker.task_results.read().expect("Failed to aquire lock");
     let first_task_result = task_results.values().next().unwrap();
-    let return_value = serde_json::from_str::<usize>(&first_task_result.result)?;
+    let return_value = match &first_task_result.outcome {
+        TaskOutcome::Success(value) => serde_json::from_str::<usize>(value)?,
+        _ => panic!("Expected the task to succeed"),
+    };
 
     assert_eq!(return_value, 6); // = 1 + 2 + 3
 
     Ok(())
 }
+
+#[test]
+fn test_message_from_older_version_is_run() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<SummationTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<SummationTask>(vec![4, 5])?;
+    let message = broker
+        .queues
+        .write()
+        .expect("Failed to aquire lock")
+        .get_mut(DEFAULT_QUEUE)
+        .and_then(|messages| messages.pop_front())
+        .expect("The task is not queued");
+
+    // The message as the first release queued it, with the signature ID only
+    // embedded in the signature.
+    let older_message = serde_json::json!({
+        "task_id": message.task_id,
+        "signature": message.signature,
+    })
+    .to_string();
+    let older_message = Message::from_json(&older_message)?;
+
+    assert_eq!(older_message.signature_id, async_result.id());
+    assert_eq!(older_message.queue, DEFAULT_QUEUE);
+    assert_eq!(older_message.priority, 0);
+    assert_eq!(older_message.attempt, 1);
+    assert!(older_message.link.is_empty());
+
+    broker.push_message(&older_message)?;
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert_eq!(async_result.try_get()?, Some(Ok(9)));
+
+    Ok(())
+}
+
+#[test]
+fn test_links_by_task_id_are_read() -> anyhow::Result<()> {
+    // Callbacks used to be linked by task ID only.
+    let older_message = serde_json::json!({
+        "task_id": "SummationTask",
+        "signature": r#"{"arg":[4,5],"id":"older"}"#,
+        "link": ["SummationTask"],
+    })
+    .to_string();
+    let older_message = Message::from_json(&older_message)?;
+
+    assert_eq!(older_message.link[0].task_id, "SummationTask");
+    assert!(!older_message.link[0].signature_id.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_failing_task_stores_error() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<DivisionTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<DivisionTask>((1, 0))?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    let result = app
+        .get_task_result(async_result.id())?
+        .expect("No result stored for the task");
+    let error = match &result.outcome {
+        TaskOutcome::Failure(error) => serde_json::from_str::<String>(error)?,
+        _ => panic!("Expected the task to fail"),
+    };
+
+    assert_eq!(error, "Division by zero");
+
+    Ok(())
+}
+
+#[test]
+fn test_panicking_task_does_not_take_down_worker() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<PanickingTask>();
+    app.register_task::<SummationTask>();
+    let app = Arc::new(app);
+
+    let panicking_result = app.queue_task::<PanickingTask>(())?;
+    let summation_result = app.queue_task::<SummationTask>(vec![1, 2])?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(
+        panicking_result.try_get()?,
+        Some(Err(TaskError::Panicked(
+            "Something went terribly wrong".to_string()
+        )))
+    );
+    assert_eq!(summation_result.try_get()?, Some(Ok(3)));
+
+    Ok(())
+}
+
+#[test]
+fn test_task_state_is_tracked() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<SummationTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<SummationTask>(vec![1])?;
+
+    let pending = async_result
+        .state()?
+        .expect("No state stored for the queued task");
+    assert_eq!(pending.state, TaskState::Pending);
+    assert!(pending.worker_id.is_none());
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    let finished = async_result
+        .state()?
+        .expect("No state stored for the finished task");
+    assert_eq!(finished.state, TaskState::Success);
+    assert_eq!(finished.worker_id, Some(worker.id.clone()));
+    assert_eq!(finished.queued_at, pending.queued_at);
+    assert!(finished.updated_at >= pending.updated_at);
+
+    Ok(())
+}
+
+#[test]
+fn test_async_result_deserializes_task_result() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<DivisionTask>();
+    let app = Arc::new(app);
+
+    let quotient = app.queue_task::<DivisionTask>((6, 3))?;
+    let division_by_zero = app.queue_task::<DivisionTask>((6, 0))?;
+
+    assert_eq!(quotient.try_get()?, None);
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(quotient.wait(Duration::from_secs(1))?, Ok(2));
+    assert_eq!(
+        division_by_zero.try_get()?,
+        Some(Err(TaskError::Failed("Division by zero".to_string())))
+    );
+
+    let signature_id = quotient.id().to_string();
+    quotient.forget()?;
+    assert!(app.get_task_result(&signature_id)?.is_none());
+    assert!(app.get_task_state(&signature_id)?.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_waiting_for_result_times_out() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<SummationTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<SummationTask>(vec![1])?;
+
+    assert!(app
+        .wait_for_result(async_result.id(), Duration::from_millis(50))?
+        .is_none());
+    assert!(matches!(
+        async_result.wait(Duration::from_millis(50)),
+        Err(Error::Timeout(signature_id)) if signature_id == async_result.id()
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_errors_can_be_matched_on() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<SummationTask>();
+    let app = Arc::new(app);
+
+    assert!(matches!(
+        app.queue_task::<DivisionTask>((1, 1)),
+        Err(Error::NotRegistered(task_id)) if task_id == "DivisionTask"
+    ));
+
+    let worker = Worker::new(app.clone())?;
+    assert!(matches!(
+        worker.take_first_task_in_queue(),
+        Err(Error::NoDueMessage)
+    ));
+
+    Ok(())
+}
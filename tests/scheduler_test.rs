@@ -0,0 +1,221 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    broker::Broker,
+    scheduler::{Schedule, Scheduler, Tz},
+    task::{Signature, Task},
+    App, Error,
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+struct CleanupTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for CleanupTask {
+    type ArgumentType = String;
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "CleanupTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+fn scheduler(app: &App<InMemoryTestBroker>) -> parsnip::Result<Scheduler<InMemoryTestBroker>> {
+    Scheduler::builder(app)
+        .add::<CleanupTask>(
+            "cleanup",
+            Schedule::interval(Duration::from_millis(100))?,
+            "tmp".to_string(),
+        )
+        .build()
+}
+
+#[test]
+fn test_interval_schedule_queues_missed_runs() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<CleanupTask>();
+
+    let scheduler = scheduler(&app)?;
+    assert_eq!(scheduler.tick()?, 0);
+
+    thread::sleep(Duration::from_millis(250));
+    assert_eq!(scheduler.tick()?, 2);
+    assert_eq!(scheduler.tick()?, 0);
+    assert_eq!(broker.queued_count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_restarted_scheduler_does_not_fire_twice() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<CleanupTask>();
+
+    let scheduler = self::scheduler(&app)?;
+    scheduler.tick()?;
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(scheduler.tick()?, 1);
+    drop(scheduler);
+
+    let restarted = self::scheduler(&app)?;
+    assert_eq!(restarted.tick()?, 0);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(restarted.tick()?, 1);
+    assert_eq!(broker.queued_count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_only_one_scheduler_is_active() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<CleanupTask>();
+
+    let active = scheduler(&app)?;
+    let standby = scheduler(&app)?;
+    active.tick()?;
+    thread::sleep(Duration::from_millis(150));
+
+    assert_eq!(standby.tick()?, 0);
+    assert_eq!(active.tick()?, 1);
+
+    // The standby takes over once the active scheduler is gone.
+    drop(active);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(standby.tick()?, 1);
+    assert_eq!(broker.queued_count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_last_run_is_only_advanced_by_lock_holder() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<CleanupTask>();
+
+    let active = scheduler(&app)?;
+    let standby = scheduler(&app)?;
+    active.tick()?;
+    let last_run = broker.get_schedule_last_run("cleanup")?;
+    let due = SystemTime::now();
+
+    assert!(!broker.advance_schedule_last_run("cleanup", last_run, due, &standby.id)?);
+    // A scheduler that read the last run before another one advanced it
+    // doesn't queue the run again.
+    assert!(!broker.advance_schedule_last_run("cleanup", Some(UNIX_EPOCH), due, &active.id)?);
+    assert!(broker.advance_schedule_last_run("cleanup", last_run, due, &active.id)?);
+    assert_eq!(broker.get_schedule_last_run("cleanup")?, Some(due));
+
+    Ok(())
+}
+
+#[test]
+fn test_catch_up_runs_are_spread_over_ticks() -> anyhow::Result<()> {
+    let broker = Arc::new(InMemoryTestBroker::new());
+    let mut app = App::with_shared_broker(broker.clone());
+
+    app.register_task::<CleanupTask>();
+
+    let scheduler = Scheduler::builder(&app)
+        .add::<CleanupTask>(
+            "cleanup",
+            Schedule::interval(Duration::from_millis(100))?,
+            "tmp".to_string(),
+        )
+        .max_catch_up_runs(1)
+        .build()?;
+    scheduler.tick()?;
+
+    thread::sleep(Duration::from_millis(250));
+    assert_eq!(scheduler.tick()?, 1);
+    assert_eq!(scheduler.tick()?, 1);
+    assert_eq!(scheduler.tick()?, 0);
+    assert_eq!(broker.queued_count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_zero_interval_is_rejected() -> anyhow::Result<()> {
+    assert!(matches!(
+        Schedule::interval(Duration::ZERO),
+        Err(Error::InvalidSchedule(_))
+    ));
+
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<CleanupTask>();
+    assert!(matches!(
+        Scheduler::builder(&app)
+            .add::<CleanupTask>(
+                "cleanup",
+                Schedule::Interval(Duration::ZERO),
+                "tmp".to_string()
+            )
+            .build(),
+        Err(Error::InvalidSchedule(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_cron_schedule_respects_timezone() -> anyhow::Result<()> {
+    let schedule = Schedule::cron("0 0 9 * * *", Tz::Europe__Oslo)?;
+
+    // From 2024-01-15 12:00 UTC, the next 09:00 in Oslo is 08:00 UTC the
+    // next day.
+    let noon = UNIX_EPOCH + Duration::from_secs(1_705_320_000);
+    assert_eq!(
+        schedule.next_after(noon),
+        Some(UNIX_EPOCH + Duration::from_secs(1_705_392_000))
+    );
+
+    assert!(matches!(
+        Schedule::cron("every morning", Tz::UTC),
+        Err(Error::InvalidSchedule(_))
+    ));
+    assert!(Schedule::interval(Duration::from_secs(1))?
+        .next_after(SystemTime::now())
+        .is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_scheduling_unregistered_task_fails() -> anyhow::Result<()> {
+    let app = App::new(InMemoryTestBroker::new());
+
+    assert!(matches!(
+        scheduler(&app),
+        Err(Error::NotRegistered(task_id)) if task_id == "CleanupTask"
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    broker::TaskState,
+    context::TaskContext,
+    result::TaskError,
+    task::{Signature, Task},
+    worker::Worker,
+    App,
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Works until asked to stop, returning how many rounds it got through.
+struct CooperativeTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for CooperativeTask {
+    type ArgumentType = ();
+    type ReturnType = u32;
+    type ErrorType = ();
+
+    const ID: &'static str = "CooperativeTask";
+
+    const SOFT_TIME_LIMIT: Option<Duration> = Some(Duration::from_millis(50));
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        let context = TaskContext::current().expect("No context for the running task");
+        let mut rounds = 0;
+        while !context.is_cancelled() {
+            thread::sleep(Duration::from_millis(5));
+            rounds += 1;
+        }
+        Ok(rounds)
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+/// Never finishes in time, and never checks whether it should stop.
+struct StuckTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for StuckTask {
+    type ArgumentType = ();
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "StuckTask";
+
+    const HARD_TIME_LIMIT: Option<Duration> = Some(Duration::from_millis(50));
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        thread::sleep(Duration::from_secs(5));
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+/// Returns the signature ID found in its context.
+struct ContextTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for ContextTask {
+    type ArgumentType = ();
+    type ReturnType = Option<String>;
+    type ErrorType = ();
+
+    const ID: &'static str = "ContextTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(_: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        Ok(TaskContext::current().map(|context| context.signature_id().to_string()))
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_soft_time_limit_cancels_task_context() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<CooperativeTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<CooperativeTask>(())?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    assert!(matches!(async_result.try_get()?, Some(Ok(rounds)) if rounds > 0));
+
+    Ok(())
+}
+
+#[test]
+fn test_hard_time_limit_fails_task_as_timed_out() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<StuckTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<StuckTask>(())?;
+
+    let started = Instant::now();
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    assert_eq!(async_result.try_get()?, Some(Err(TaskError::TimedOut)));
+    assert_eq!(
+        async_result.state()?.map(|info| info.state),
+        Some(TaskState::Failure)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_context_is_available_without_time_limits() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<ContextTask>();
+    let app = Arc::new(app);
+
+    let async_result = app.queue_task::<ContextTask>(())?;
+
+    let worker = Worker::new(app.clone())?;
+    worker.take_first_task_in_queue()?;
+
+    assert_eq!(
+        async_result.try_get()?,
+        Some(Ok(Some(async_result.id().to_string())))
+    );
+    assert!(TaskContext::current().is_none());
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::InMemoryTestBroker;
+use parsnip::{
+    self,
+    messages::Command,
+    task::{Signature, Task},
+    worker::Worker,
+    App,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Sleeps for a while, keeping track of how many instances run at once.
+struct SleepTask {
+    called_with_signature: Signature<Self>,
+}
+
+impl Task for SleepTask {
+    type ArgumentType = u64;
+    type ReturnType = ();
+    type ErrorType = ();
+
+    const ID: &'static str = "SleepTask";
+
+    fn from_signature(signature: Signature<Self>) -> Self {
+        Self {
+            called_with_signature: signature,
+        }
+    }
+
+    fn run(arg: &Self::ArgumentType) -> Result<Self::ReturnType, Self::ErrorType> {
+        let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+        MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(*arg));
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn signature(&self) -> &Signature<Self> {
+        &self.called_with_signature
+    }
+}
+
+#[test]
+fn test_worker_runs_tasks_in_parallel() -> anyhow::Result<()> {
+    let mut app = App::new(InMemoryTestBroker::new());
+
+    app.register_task::<SleepTask>();
+    let app = Arc::new(app);
+
+    let worker = Worker::builder(app.clone())
+        .concurrency(2)
+        .poll_interval(Duration::from_millis(10))
+        .build()?;
+
+    let worker_id = worker.id.clone();
+    let listener = thread::spawn(move || worker.listen_for_messages());
+
+    let async_results = (0..4)
+        .map(|_| app.queue_task::<SleepTask>(100))
+        .collect::<parsnip::Result<Vec<_>>>()?;
+    for async_result in &async_results {
+        assert_eq!(async_result.wait(Duration::from_secs(5))?, Ok(()));
+    }
+
+    // The whole pool is registered as a single worker, and stops on a single
+    // command.
+    assert_eq!(app.list_workers()?.map(|workers| workers.len()), Some(1));
+    app.queue_command(&Command::StopWorker, &worker_id)?;
+    listener.join().expect("Worker thread panicked")?;
+
+    assert_eq!(MAX_RUNNING.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}